@@ -1,10 +1,16 @@
+use std::marker::PhantomData;
 use std::os::unix::net::UnixStream;
 use std::path::Path;
+use std::process::Child;
+use std::thread;
+use std::time::Instant;
 
 use crate::communication::*;
+use crate::daemon;
 use crate::error::{ClientError, ClientResult};
 use crate::log;
 use crate::names::Name;
+use crate::timing::Duration;
 
 pub struct Client {
     socket: UnixStream,
@@ -18,6 +24,71 @@ impl Client {
         Ok(Client { socket })
     }
 
+    /// Connects to the daemon, starting one in the background if nobody is
+    /// answering on the socket.
+    ///
+    /// The daemon is started by running the current executable, so this is
+    /// intended for use by the command-line interface. To run a different
+    /// executable, use [`Client::connect_or_spawn_with`].
+    pub fn connect_or_spawn(socket_path: &Path) -> ClientResult<Self> {
+        let executable =
+            std::env::current_exe().map_err(|error| ClientError::SpawnDaemonError(error.into()))?;
+        Self::connect_or_spawn_with(socket_path, &executable)
+    }
+
+    /// Connects to the daemon, starting one in the background with the given
+    /// executable if nobody is answering on the socket.
+    ///
+    /// The daemon's output is written to `daemon.log`, next to the socket.
+    pub fn connect_or_spawn_with(socket_path: &Path, executable: &Path) -> ClientResult<Self> {
+        if let Ok(client) = Self::connect_and_ping(socket_path) {
+            return Ok(client);
+        }
+        log::info!(event = "SPAWN_DAEMON", socket = socket_path);
+        let daemon = daemon::spawn(executable, socket_path, &[])
+            .map_err(|error| ClientError::SpawnDaemonError(error.into()))?;
+        Self::connect_when_ready(socket_path, daemon)
+    }
+
+    /// Waits for a daemon that we started to answer on the socket, and then
+    /// connects to it.
+    ///
+    /// If the daemon stops first, we stop waiting, unless it stopped because
+    /// another daemon got there first, in which case we wait for that one.
+    pub fn connect_when_ready(socket_path: &Path, mut daemon: Child) -> ClientResult<Self> {
+        let start_time = Instant::now();
+        loop {
+            Duration::QUANTUM.sleep();
+            if let Ok(client) = Self::connect_and_ping(socket_path) {
+                // we wait in the background so that the process is cleaned up
+                // if it stops while we're still around
+                thread::spawn(move || daemon.wait());
+                return Ok(client);
+            }
+            match daemon.try_wait() {
+                Ok(None) => {}
+                Ok(Some(status))
+                    if status.code() == Some(daemon::ALREADY_RUNNING_EXIT_CODE.into()) => {}
+                Ok(Some(status)) => {
+                    return Err(ClientError::SpawnDaemonExited(status.to_string()));
+                }
+                Err(error) => {
+                    return Err(ClientError::SpawnDaemonError(error.into()));
+                }
+            }
+            if start_time.elapsed() > Duration::DAEMON_START_TIMEOUT.into() {
+                return Err(ClientError::SpawnDaemonTimeOut);
+            }
+        }
+    }
+
+    /// Connects to the daemon, and checks that it is answering.
+    pub fn connect_and_ping(socket_path: &Path) -> ClientResult<Self> {
+        let mut client = Self::connect_to(socket_path)?;
+        client.ping()?;
+        Ok(client)
+    }
+
     pub fn ping(&mut self) -> ClientResult<()> {
         self.send(&Request::Ping).map(|PingResponse::Pong| ())
     }
@@ -38,6 +109,81 @@ impl Client {
             })
     }
 
+    /// Stops every service in the group, most recently started first, and
+    /// returns how each one exited.
+    pub fn stop_group(&mut self, instruction: StopGroup) -> ClientResult<Vec<(Name, ExitStatus)>> {
+        self.send(&Request::StopGroup(instruction))
+            .and_then(|response| match response {
+                StopManyResponse::Success(exit_statuses) => Ok(exit_statuses),
+                StopManyResponse::Failure(error) => Err(ClientError::DaemonError(error)),
+            })
+    }
+
+    /// Stops every service matching the selector, most recently started
+    /// first, and returns how each one exited.
+    pub fn stop_selected(
+        &mut self,
+        instruction: StopSelected,
+    ) -> ClientResult<Vec<(Name, ExitStatus)>> {
+        self.send(&Request::StopSelected(instruction))
+            .and_then(|response| match response {
+                StopManyResponse::Success(exit_statuses) => Ok(exit_statuses),
+                StopManyResponse::Failure(error) => Err(ClientError::DaemonError(error)),
+            })
+    }
+
+    pub fn list(&mut self) -> ClientResult<Vec<ServiceDetails>> {
+        self.list_selected(List {
+            selector: Default::default(),
+        })
+    }
+
+    pub fn list_selected(&mut self, instruction: List) -> ClientResult<Vec<ServiceDetails>> {
+        self.send(&Request::List(instruction))
+            .and_then(|response| match response {
+                ListResponse::Success(details) => Ok(details),
+                ListResponse::Failure(error) => Err(ClientError::DaemonError(error)),
+            })
+    }
+
+    /// Retrieves the output of a service so far, a chunk at a time.
+    ///
+    /// If the chunks are dropped before they have all been read, the rest are
+    /// read and thrown away, so that the connection can be used again.
+    pub fn logs(
+        &mut self,
+        instruction: Logs,
+    ) -> ClientResult<impl Iterator<Item = ClientResult<Vec<u8>>> + '_> {
+        let responses = self.send_streamed(&Request::Logs(instruction))?;
+        Ok(responses.filter_map(|response| match response {
+            Ok(LogsResponse::Output(output)) => Some(Ok(output.into())),
+            Ok(LogsResponse::End) => None,
+            Ok(LogsResponse::Failure(error)) => Some(Err(ClientError::DaemonError(error))),
+            Err(error) => Some(Err(error)),
+        }))
+    }
+
+    /// Retrieves the output of a service, and then continues to provide more
+    /// output as it is written, until the service stops.
+    ///
+    /// This could go on forever, so it takes the connection with it, rather
+    /// than leaving it in an unknown state if the output is dropped early.
+    pub fn follow_logs(
+        mut self,
+        instruction: Logs,
+    ) -> ClientResult<impl Iterator<Item = ClientResult<Vec<u8>>>> {
+        self.write_request(&Request::FollowLogs(instruction))?;
+        let mut finished = false;
+        let responses = std::iter::from_fn(move || read_streamed(&mut self.socket, &mut finished));
+        Ok(responses.filter_map(|response| match response {
+            Ok(LogsResponse::Output(output)) if output.is_empty() => None,
+            Ok(LogsResponse::Output(output)) => Some(Ok(output.into())),
+            Ok(LogsResponse::End) => None,
+            Ok(LogsResponse::Failure(error)) => Some(Err(ClientError::DaemonError(error))),
+            Err(error) => Some(Err(error)),
+        }))
+    }
+
     pub fn shutdown(&mut self) -> ClientResult<()> {
         self.send(&Request::Shutdown)
             .map(|response| match response {
@@ -46,19 +192,81 @@ impl Client {
     }
 
     fn send<R: Response + serde::Serialize>(&mut self, request: &Request) -> ClientResult<R> {
-        log::debug!(request);
-        request
-            .write_to(&mut self.socket)
-            .map_err(ClientError::CommunicationError)?;
+        self.write_request(request)?;
         let response = R::read_from(&mut self.socket).map_err(ClientError::CommunicationError)?;
         log::debug!(response);
         Ok(response)
     }
+
+    fn send_streamed<R: StreamedResponse + serde::Serialize>(
+        &mut self,
+        request: &Request,
+    ) -> ClientResult<StreamedResponses<R>> {
+        self.write_request(request)?;
+        Ok(StreamedResponses {
+            socket: &mut self.socket,
+            finished: false,
+            response_type: PhantomData,
+        })
+    }
+
+    fn write_request(&mut self, request: &Request) -> ClientResult<()> {
+        log::debug!(request);
+        request
+            .write_to(&mut self.socket)
+            .map_err(ClientError::CommunicationError)
+    }
+}
+
+// Reads the next part of a streamed response, until the final part.
+fn read_streamed<R: StreamedResponse + serde::Serialize>(
+    socket: &mut UnixStream,
+    finished: &mut bool,
+) -> Option<ClientResult<R>> {
+    if *finished {
+        return None;
+    }
+    match R::read_from(socket) {
+        Ok(response) => {
+            log::debug!(response);
+            *finished = response.is_final();
+            Some(Ok(response))
+        }
+        Err(error) => {
+            *finished = true;
+            Some(Err(ClientError::CommunicationError(error)))
+        }
+    }
+}
+
+struct StreamedResponses<'a, R: StreamedResponse + serde::Serialize> {
+    socket: &'a mut UnixStream,
+    finished: bool,
+    response_type: PhantomData<R>,
+}
+
+impl<'a, R: StreamedResponse + serde::Serialize> Iterator for StreamedResponses<'a, R> {
+    type Item = ClientResult<R>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        read_streamed(self.socket, &mut self.finished)
+    }
+}
+
+// Reads whatever is left, so that the next request doesn't get the rest of
+// this response by mistake.
+impl<'a, R: StreamedResponse + serde::Serialize> Drop for StreamedResponses<'a, R> {
+    fn drop(&mut self) {
+        for _ in self {}
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::daemon::Daemon;
+    use crate::output::Stream;
+    use crate::services::{Program, Service};
+    use crate::test_helpers::*;
 
     use super::*;
 
@@ -90,4 +298,149 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_lists_services() -> anyhow::Result<()> {
+        let socket_dir = tempfile::Builder::new()
+            .prefix("sandcastles-test")
+            .tempdir()?;
+        let socket_path = socket_dir.path().join("socket");
+        let daemon = Daemon::start_on_socket(socket_path)?;
+        let mut client = Client::connect_to(daemon.socket())?;
+
+        let details = client.list()?;
+
+        assert_eq!(details, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    #[ntest::timeout(5000)]
+    fn test_reads_logs() -> anyhow::Result<()> {
+        let socket_dir = tempfile::Builder::new()
+            .prefix("sandcastles-test")
+            .tempdir()?;
+        let socket_path = socket_dir.path().join("socket");
+        let daemon = Daemon::start_on_socket(socket_path)?;
+        let mut client = Client::connect_to(daemon.socket())?;
+        let name = client.start(Start {
+            service: Service::Program(Program {
+                command: "sh".into(),
+                arguments: vec!["-c".into(), "echo one; echo two; sleep 10".into()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        })?;
+        let logs = Logs {
+            name,
+            stream: Stream::Stdout,
+            tail: None,
+        };
+
+        eventually(|| {
+            let output = client
+                .logs(logs.clone())?
+                .collect::<ClientResult<Vec<Vec<u8>>>>()?;
+            test_eq(output.concat(), b"one\ntwo\n".to_vec())
+        })?;
+        // the connection is still usable afterwards
+        client.ping()?;
+        Ok(())
+    }
+
+    #[test]
+    #[ntest::timeout(5000)]
+    fn test_can_stop_reading_logs_early() -> anyhow::Result<()> {
+        let socket_dir = tempfile::Builder::new()
+            .prefix("sandcastles-test")
+            .tempdir()?;
+        let socket_path = socket_dir.path().join("socket");
+        let daemon = Daemon::start_on_socket(socket_path)?;
+        let mut client = Client::connect_to(daemon.socket())?;
+        let name = client.start(Start {
+            service: Service::Program(Program {
+                command: "sh".into(),
+                arguments: vec!["-c".into(), "seq 100000; sleep 10".into()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        })?;
+        let logs = Logs {
+            name,
+            stream: Stream::Stdout,
+            tail: None,
+        };
+        // enough output for several chunks
+        eventually(|| {
+            let output = client
+                .logs(logs.clone())?
+                .collect::<ClientResult<Vec<Vec<u8>>>>()?;
+            test_eq(output.concat().ends_with(b"\n100000\n"), true)
+        })?;
+
+        let first = client.logs(logs)?.next().transpose()?;
+        client.ping()?;
+
+        assert!(first.map_or(false, |chunk| chunk.starts_with(b"1\n2\n")));
+        Ok(())
+    }
+
+    #[test]
+    #[ntest::timeout(5000)]
+    fn test_follows_logs() -> anyhow::Result<()> {
+        let socket_dir = tempfile::Builder::new()
+            .prefix("sandcastles-test")
+            .tempdir()?;
+        let socket_path = socket_dir.path().join("socket");
+        let daemon = Daemon::start_on_socket(socket_path)?;
+        let mut client = Client::connect_to(daemon.socket())?;
+        let name = client.start(Start {
+            service: Service::Program(Program {
+                command: "bash".into(),
+                arguments: vec!["-c".into(), "echo one; sleep 0.5; echo two".into()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        })?;
+
+        let output = client
+            .follow_logs(Logs {
+                name,
+                stream: Stream::Stdout,
+                tail: None,
+            })?
+            .collect::<ClientResult<Vec<Vec<u8>>>>()?;
+
+        assert_eq!(output.concat(), b"one\ntwo\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_the_daemon_notices_when_services_exit() -> anyhow::Result<()> {
+        let socket_dir = tempfile::Builder::new()
+            .prefix("sandcastles-test")
+            .tempdir()?;
+        let socket_path = socket_dir.path().join("socket");
+        let daemon = Daemon::start_on_socket(socket_path)?;
+        let mut client = Client::connect_to(daemon.socket())?;
+        client.start(Start {
+            service: Service::Program(Program {
+                command: "bash".into(),
+                arguments: vec!["-c".into(), "sleep 0.5; exit 1".into()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        })?;
+
+        eventually(|| {
+            let exits = client
+                .list()?
+                .into_iter()
+                .map(|details| details.last_exit.map(|exit| exit.status))
+                .collect();
+            test_eq(exits, vec![Some(ExitStatus::ExitedWithCode(1))])
+        })?;
+
+        Ok(())
+    }
 }
@@ -1,8 +1,13 @@
 use std::io;
+use std::os::unix::process::ExitStatusExt;
 
 use crate::error::{CommunicationError, CommunicationResult, DaemonError};
+use crate::labels::{Labels, Selector};
 use crate::names::Name;
+use crate::output::Stream;
+use crate::restart::RestartPolicy;
 use crate::services::Service;
+use crate::timing::Duration;
 use crate::wait::WaitFor;
 
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
@@ -10,11 +15,21 @@ pub(crate) enum Request {
     Ping,
     Start(Start),
     Stop(Stop),
+    StopGroup(StopGroup),
+    StopSelected(StopSelected),
+    List(List),
+    Logs(Logs),
+    FollowLogs(Logs),
     Shutdown,
 }
 
 pub trait Response: Ship {}
 
+/// A response that is sent in several parts, ending with a final part.
+pub trait StreamedResponse: Response {
+    fn is_final(&self) -> bool;
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub(crate) enum PingResponse {
     Pong,
@@ -38,6 +53,41 @@ pub(crate) enum StopResponse {
 
 impl Response for StopResponse {}
 
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub(crate) enum StopManyResponse {
+    Success(Vec<(Name, ExitStatus)>),
+    Failure(DaemonError),
+}
+
+impl Response for StopManyResponse {}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub(crate) enum ListResponse {
+    Success(Vec<ServiceDetails>),
+    Failure(DaemonError),
+}
+
+impl Response for ListResponse {}
+
+// Answers both `Logs` and `FollowLogs`, a chunk of output at a time.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub(crate) enum LogsResponse {
+    Output(bstr::BString),
+    End,
+    Failure(DaemonError),
+}
+
+impl Response for LogsResponse {}
+
+impl StreamedResponse for LogsResponse {
+    fn is_final(&self) -> bool {
+        match self {
+            Self::Output(_) => false,
+            Self::End | Self::Failure(_) => true,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub(crate) enum ShutdownResponse {
     Success,
@@ -52,6 +102,24 @@ pub enum ExitStatus {
     ExitedWithSignal(u8),
 }
 
+impl From<std::process::ExitStatus> for ExitStatus {
+    fn from(value: std::process::ExitStatus) -> Self {
+        match value.code() {
+            None => match value.signal() {
+                None => Self::None,
+                Some(signal) => match u8::try_from(signal).ok() {
+                    None => Self::None,
+                    Some(signal) => Self::ExitedWithSignal(signal),
+                },
+            },
+            Some(code) => match u8::try_from(code).ok() {
+                None => Self::None,
+                Some(code) => Self::ExitedWithCode(code),
+            },
+        }
+    }
+}
+
 impl From<ExitStatus> for std::process::ExitCode {
     fn from(value: ExitStatus) -> Self {
         match value {
@@ -62,11 +130,24 @@ impl From<ExitStatus> for std::process::ExitCode {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct Start {
     pub name: Option<Name>,
     pub service: Service,
     pub wait: WaitFor,
+    /// How long to wait for the service to be ready.
+    ///
+    /// If this is not set, the daemon's default is used.
+    pub timeout: Option<Duration>,
+    /// The process that owns the service.
+    ///
+    /// If this is set, the service is stopped when the owner exits.
+    pub owner: Option<u32>,
+    /// The group the service belongs to, so it can be stopped with the rest
+    /// of the group.
+    pub group: Option<Name>,
+    pub labels: Labels,
+    pub restart: RestartPolicy,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
@@ -74,6 +155,62 @@ pub struct Stop {
     pub name: Name,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct StopGroup {
+    pub group: Name,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct StopSelected {
+    pub selector: Selector,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct List {
+    pub selector: Selector,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Logs {
+    pub name: Name,
+    pub stream: Stream,
+    pub tail: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ServiceDetails {
+    pub name: Name,
+    pub service: Service,
+    pub process_id: u32,
+    pub start_time: chrono::DateTime<chrono::Utc>,
+    pub wait: WaitFor,
+    pub state: ServiceState,
+    pub restarts: u32,
+    pub last_exit: Option<Exit>,
+    pub owner: Option<u32>,
+    pub group: Option<Name>,
+    pub labels: Labels,
+}
+
+/// What a service is doing. If it has exited, `last_exit` says how.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceState {
+    /// Started, but not yet ready.
+    Starting,
+    Running,
+    /// Waiting to be restarted.
+    Restarting,
+    /// Stopped, and will not be restarted.
+    Exited,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Exit {
+    pub status: ExitStatus,
+    pub time: chrono::DateTime<chrono::Utc>,
+}
+
 pub trait Ship: Sized {
     fn read_from(reader: impl io::Read) -> CommunicationResult<Self>;
 
@@ -121,8 +258,13 @@ mod tests {
 
     use anyhow::Context;
 
-    use crate::services::programs::Program;
-    use crate::timing::Duration;
+    use crate::error::{
+        InvalidDirectory, InvalidEnvironmentFile, InvalidNixEnvironment, InvalidPattern, InvalidUrl,
+    };
+    use crate::ports::Port;
+    use crate::services::nix_programs::{NixEnvironment, NixProgram};
+    use crate::services::programs::{EnvironmentPolicy, Program};
+    use crate::timing::{Duration, DurationUnit};
 
     use super::*;
 
@@ -138,10 +280,71 @@ mod tests {
                         ("ONE".into(), "1".into()),
                         ("TWO".into(), "2".into()),
                     ]),
+                    base_environment: BTreeMap::from([("PATH".into(), "/bin".into())]),
+                    environment_policy: EnvironmentPolicy::Allowlist(vec![
+                        "HOME".into(),
+                        "LANG".into(),
+                    ]),
+                    environment_files: vec![".env".into(), ".env.local".into()],
+                    working_directory: Some("/path/to/project".into()),
                 }),
                 wait: WaitFor::Time {
                     duration: Duration::QUANTUM,
                 },
+                timeout: Some(Duration::of(5, DurationUnit::Seconds)),
+                owner: Some(1),
+                group: Some("tests".parse()?),
+                labels: Labels::from([("project".into(), "sandcastles".into())]),
+                restart: RestartPolicy::OnFailure {
+                    max_retries: Some(3),
+                    backoff: Duration::QUANTUM,
+                },
+            }),
+            Request::Start(Start {
+                name: Some("nix".parse()?),
+                service: Service::Nix(NixProgram {
+                    environment: NixEnvironment::Flake(".#dev".to_owned()),
+                    program: Program {
+                        command: "program".into(),
+                        arguments: Default::default(),
+                        environment: Default::default(),
+                        base_environment: Default::default(),
+                        environment_policy: Default::default(),
+                        environment_files: Default::default(),
+                        working_directory: Some("/path/to/project".into()),
+                    },
+                }),
+                wait: WaitFor::AMoment,
+                timeout: None,
+                owner: None,
+                group: None,
+                labels: Default::default(),
+                restart: RestartPolicy::Never,
+            }),
+            Request::Stop(Stop {
+                name: "goodbye".parse()?,
+            }),
+            Request::StopGroup(StopGroup {
+                group: "tests".parse()?,
+            }),
+            Request::StopSelected(StopSelected {
+                selector: "project=sandcastles,suite!=slow".parse()?,
+            }),
+            Request::List(List {
+                selector: Selector::default(),
+            }),
+            Request::List(List {
+                selector: "project=sandcastles".parse()?,
+            }),
+            Request::Logs(Logs {
+                name: "chatty".parse()?,
+                stream: Stream::Stderr,
+                tail: Some(10),
+            }),
+            Request::FollowLogs(Logs {
+                name: "chatty".parse()?,
+                stream: Stream::Stdout,
+                tail: None,
             }),
             Request::Shutdown,
         ];
@@ -158,6 +361,8 @@ mod tests {
     #[test]
     fn test_errors_are_serializable_and_deserializable() -> anyhow::Result<()> {
         let errors = vec![
+            DaemonError::AlreadyRunning,
+            DaemonError::LockError(io::Error::new(io::ErrorKind::Other, "zero").into()),
             DaemonError::SocketCreationError(io::Error::new(io::ErrorKind::Other, "one").into()),
             DaemonError::SocketConfigurationError(
                 io::Error::new(io::ErrorKind::Other, "two").into(),
@@ -175,7 +380,41 @@ mod tests {
                 process_id: 7,
                 inner: io::Error::new(io::ErrorKind::Other, "seven").into(),
             },
-            DaemonError::TimeOut,
+            DaemonError::TimeOut(WaitFor::All {
+                conditions: vec![
+                    WaitFor::Port { port: Port(8080) },
+                    WaitFor::Time {
+                        duration: Duration::QUANTUM,
+                    },
+                ],
+            }),
+            DaemonError::CaptureOutputError(io::Error::new(io::ErrorKind::Other, "eight").into()),
+            DaemonError::ReadOutputError(io::Error::new(io::ErrorKind::Other, "nine").into()),
+            DaemonError::OutputNotCapturedError,
+            DaemonError::InvalidPatternError(InvalidPattern {
+                pattern: "(".to_owned(),
+                message: "ten".to_owned(),
+            }),
+            DaemonError::InvalidUrlError(InvalidUrl {
+                url: "ftp://localhost/".to_owned(),
+                message: "ten and a half".to_owned(),
+            }),
+            DaemonError::NothingToWaitForError,
+            DaemonError::RestoreStateError(io::Error::new(io::ErrorKind::Other, "eleven").into()),
+            DaemonError::OwnerNotRunningError,
+            DaemonError::WorkingDirectoryError(InvalidDirectory {
+                path: "/no/such/directory".into(),
+                message: "twelve".to_owned(),
+            }),
+            DaemonError::EnvironmentFileError(InvalidEnvironmentFile {
+                path: "/path/to/.env".into(),
+                message: "thirteen".to_owned(),
+            }),
+            DaemonError::NixEnvironmentError(InvalidNixEnvironment {
+                environment: ".#dev".to_owned(),
+                message: "fourteen".to_owned(),
+            }),
+            DaemonError::ShuttingDownError,
         ];
 
         for error in errors {
@@ -1,20 +1,30 @@
+use std::ffi::OsString;
 use std::fs;
 use std::io;
 use std::mem;
+use std::os::fd::AsRawFd;
 use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::CommandExt;
 use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
 use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread;
+use std::time::Instant;
 
 use crate::awaiter::Awaiter;
-use crate::communication::{PingResponse, Request, Ship, ShutdownResponse, StartResponse};
+use crate::client::Client;
+use crate::communication::{
+    ListResponse, LogsResponse, PingResponse, Request, Ship, ShutdownResponse, StartResponse,
+    StopManyResponse,
+};
 use crate::error::{CommunicationError, DaemonError, DaemonResult};
 use crate::log;
-use crate::supervisor::Supervisor;
+use crate::supervisor::{Supervisor, SupervisorOptions};
 use crate::timing::Duration;
 use crate::StopResponse;
 
@@ -23,6 +33,14 @@ enum StopHandle {
     Awaiter(Awaiter),
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct DaemonOptions {
+    pub supervisor: SupervisorOptions,
+    /// If set, the daemon shuts itself down once it has had nothing to
+    /// supervise and no clients connected for this long.
+    pub idle_timeout: Option<Duration>,
+}
+
 pub struct Daemon {
     socket_path: PathBuf,
     stop_handle: Mutex<StopHandle>,
@@ -31,19 +49,68 @@ pub struct Daemon {
 
 impl Daemon {
     pub fn start_on_socket(socket_path: PathBuf) -> DaemonResult<Self> {
-        Self::start(socket_path, Supervisor::new())
+        Self::start_on_socket_with_options(socket_path, DaemonOptions::default())
+    }
+
+    /// Starts a daemon with the given options.
+    ///
+    /// If no state directory is provided, state is kept next to the socket.
+    pub fn start_on_socket_with_options(
+        socket_path: PathBuf,
+        mut options: DaemonOptions,
+    ) -> DaemonResult<Self> {
+        if options.supervisor.state_directory.is_none() {
+            options.supervisor.state_directory =
+                socket_path.parent().map(|directory| directory.to_owned());
+        }
+        Self::start_supervising(
+            socket_path,
+            Supervisor::with_options(options.supervisor),
+            options.idle_timeout,
+        )
     }
 
     pub fn start(socket_path: PathBuf, supervisor: Supervisor) -> DaemonResult<Self> {
+        Self::start_supervising(socket_path, supervisor, None)
+    }
+
+    fn start_supervising(
+        socket_path: PathBuf,
+        supervisor: Supervisor,
+        idle_timeout: Option<Duration>,
+    ) -> DaemonResult<Self> {
+        let lock_file = lock(&socket_path)?;
+        if socket_path.exists() {
+            if Client::connect_and_ping(&socket_path).is_ok() {
+                return Err(DaemonError::AlreadyRunning);
+            }
+            // nobody is listening, so the socket was left behind by a crash
+            log::warning!(event = "STALE_SOCKET", socket = socket_path);
+            fs::remove_file(&socket_path)
+                .map_err(|error| DaemonError::SocketCreationError(error.into()))?;
+        }
         let listener = UnixListener::bind(&socket_path)
             .map_err(|error| DaemonError::SocketCreationError(error.into()))?;
         listener
             .set_nonblocking(true)
             .map_err(|error| DaemonError::SocketConfigurationError(error.into()))?;
+        supervisor
+            .restore()
+            .unwrap_or_else(|error| log::error!(event = "RESTORE", error));
+        let claim = Claim {
+            socket_path: socket_path.clone(),
+            lock_file,
+        };
         let stop_signal = Arc::new(AtomicBool::new(false));
         let stop_signal_for_start = Arc::clone(&stop_signal);
         let thread_handle = thread::spawn(move || {
-            start(&supervisor, listener, stop_signal_for_start.as_ref());
+            start(
+                &supervisor,
+                listener,
+                claim,
+                stop_signal_for_start.as_ref(),
+                idle_timeout,
+            );
         });
         Ok(Self {
             socket_path,
@@ -94,20 +161,134 @@ impl Drop for Daemon {
     fn drop(&mut self) {
         self.stop();
         self.wait();
+    }
+}
+
+// The socket, along with the lock that makes it ours.
+struct Claim {
+    socket_path: PathBuf,
+    // held for as long as the daemon is running
+    lock_file: fs::File,
+}
+
+impl Claim {
+    // Removes the socket, and only then releases the lock, so that a new
+    // daemon can start straight away without finding our socket.
+    fn release(self) {
         fs::remove_file(&self.socket_path)
             .unwrap_or_else(|error| log::error!(event = "SHUTDOWN", error = error.log()));
+        drop(self.lock_file);
+    }
+}
+
+/// The exit code of a daemon that finds another daemon already using its
+/// socket.
+pub const ALREADY_RUNNING_EXIT_CODE: u8 = 3;
+
+/// Starts a daemon in the background, by running the given executable with
+/// `daemon --foreground` and any further arguments.
+///
+/// The daemon runs in a new session, so that it isn't affected by anything
+/// that happens to us, such as the terminal closing. Its output is written to
+/// `daemon.log`, next to the socket.
+///
+/// We only fork once, rather than twice, so that we can tell if the daemon
+/// fails to start. This leaves the daemon leading its session, so it could
+/// acquire a controlling terminal by opening one, but it never does: its
+/// standard streams are redirected before it starts, and the services it
+/// runs are not session leaders.
+pub fn spawn(executable: &Path, socket_path: &Path, arguments: &[OsString]) -> io::Result<Child> {
+    let directory = socket_path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(directory)?;
+    let log_file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(directory.join("daemon.log"))?;
+    let mut command = Command::new(executable);
+    command
+        .arg("--socket-path")
+        .arg(socket_path)
+        .arg("daemon")
+        .arg("--foreground")
+        .args(arguments)
+        .stdin(Stdio::null())
+        .stdout(log_file.try_clone()?)
+        .stderr(log_file);
+    unsafe {
+        command.pre_exec(|| {
+            nix::unistd::setsid()?;
+            Ok(())
+        });
+    }
+    command.spawn()
+}
+
+// Takes an exclusive lock on a file next to the socket, so that two daemons
+// can't race to use the same socket.
+//
+// The lock file is never removed, as removing it would let another daemon lock
+// a new file at the same path while we still hold the lock on the old one.
+fn lock(socket_path: &Path) -> DaemonResult<fs::File> {
+    let mut lock_path = socket_path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let lock_file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .open(lock_path)
+        .map_err(|error| DaemonError::LockError(error.into()))?;
+    match nix::fcntl::flock(
+        lock_file.as_raw_fd(),
+        nix::fcntl::FlockArg::LockExclusiveNonblock,
+    ) {
+        Ok(()) => Ok(lock_file),
+        Err(nix::errno::Errno::EWOULDBLOCK) => Err(DaemonError::AlreadyRunning),
+        Err(error) => Err(DaemonError::LockError(
+            io::Error::from_raw_os_error(error as i32).into(),
+        )),
     }
 }
 
-fn start(supervisor: &Supervisor, listener: UnixListener, internal_stop_signal: &AtomicBool) {
+fn start(
+    supervisor: &Supervisor,
+    listener: UnixListener,
+    claim: Claim,
+    internal_stop_signal: &AtomicBool,
+    idle_timeout: Option<Duration>,
+) {
     log::debug!(event = "STARTED");
+    let reaper_stop_signal = AtomicBool::new(false);
+    thread::scope(|scope| {
+        scope.spawn(|| reap(supervisor, &reaper_stop_signal));
+        accept(
+            supervisor,
+            listener,
+            claim,
+            internal_stop_signal,
+            idle_timeout,
+        );
+        reaper_stop_signal.store(true, Ordering::Relaxed);
+    });
+    log::debug!(event = "STOPPED");
+}
+
+fn accept(
+    supervisor: &Supervisor,
+    listener: UnixListener,
+    claim: Claim,
+    internal_stop_signal: &AtomicBool,
+    idle_timeout: Option<Duration>,
+) {
     let (stop_sender, stop_receiver) = mpsc::channel();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let mut idle_since = None;
     for incoming in listener.incoming() {
         match incoming {
             Ok(stream) => {
                 let supervisor_for_connection = supervisor.clone();
                 let stop_sender_for_connection = stop_sender.clone();
+                let connection = OpenConnection::new(&connections);
                 thread::spawn(move || {
+                    let _connection = connection;
                     stream
                         .set_nonblocking(false)
                         .map_err(|error| DaemonError::SocketConfigurationError(error.into()))
@@ -134,8 +315,74 @@ fn start(supervisor: &Supervisor, listener: UnixListener, internal_stop_signal:
         if stop_requested(supervisor, internal_stop_signal, &stop_receiver) {
             break;
         }
+        if let Some(idle_timeout) = idle_timeout {
+            if idle_for(supervisor, &connections, &mut idle_since) >= idle_timeout.into() {
+                log::info!(event = "IDLE_SHUTDOWN");
+                // once the socket is gone, nobody else can connect, and a new
+                // daemon can start; anyone who connected just before then is
+                // turned away, so that they try again with the new daemon
+                claim.release();
+                turn_away(&listener);
+                return;
+            }
+        }
+    }
+    claim.release();
+}
+
+// Closes every connection that is still waiting to be accepted.
+fn turn_away(listener: &UnixListener) {
+    while let Ok((stream, _)) = listener.accept() {
+        log::debug!(event = "TURNED_AWAY");
+        drop(stream);
+    }
+}
+
+// Counts a connection as open for as long as this is alive.
+struct OpenConnection(Arc<AtomicUsize>);
+
+impl OpenConnection {
+    fn new(connections: &Arc<AtomicUsize>) -> Self {
+        connections.fetch_add(1, Ordering::SeqCst);
+        Self(Arc::clone(connections))
+    }
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Works out how long we've had nothing to do: no services to supervise, and
+// no clients connected, which means no requests in flight.
+fn idle_for(
+    supervisor: &Supervisor,
+    connections: &AtomicUsize,
+    idle_since: &mut Option<Instant>,
+) -> std::time::Duration {
+    let idle = connections.load(Ordering::SeqCst) == 0
+        && supervisor.is_idle().unwrap_or_else(|error| {
+            log::error!(event = "IDLE", error);
+            false
+        });
+    if idle {
+        idle_since.get_or_insert_with(Instant::now).elapsed()
+    } else {
+        *idle_since = None;
+        std::time::Duration::ZERO
+    }
+}
+
+// Periodically checks on every service, so that we notice when one stops
+// without being asked to.
+fn reap(supervisor: &Supervisor, stop_signal: &AtomicBool) {
+    while !stop_signal.load(Ordering::Relaxed) {
+        supervisor
+            .reap()
+            .unwrap_or_else(|error| log::error!(event = "REAP", error));
+        Duration::QUANTUM.sleep();
     }
-    log::debug!(event = "STOPPED");
 }
 
 fn handle_connection(
@@ -185,6 +432,94 @@ fn handle_connection(
                     .write_to(&mut stream)
                     .map_err(DaemonError::CommunicationError)
             }
+            Request::StopGroup(instruction) => {
+                log::info!(event = "STOP_GROUP", instruction);
+                let response = match supervisor.stop_group(&instruction) {
+                    Ok(exit_statuses) => StopManyResponse::Success(exit_statuses),
+                    Err(error) => {
+                        log::warning!(event = "STOP_GROUP", instruction, error);
+                        StopManyResponse::Failure(error)
+                    }
+                };
+                log::debug!(event = "HANDLE", response);
+                response
+                    .write_to(&mut stream)
+                    .map_err(DaemonError::CommunicationError)
+            }
+            Request::StopSelected(instruction) => {
+                log::info!(event = "STOP_SELECTED", instruction);
+                let response = match supervisor.stop_selected(&instruction) {
+                    Ok(exit_statuses) => StopManyResponse::Success(exit_statuses),
+                    Err(error) => {
+                        log::warning!(event = "STOP_SELECTED", instruction, error);
+                        StopManyResponse::Failure(error)
+                    }
+                };
+                log::debug!(event = "HANDLE", response);
+                response
+                    .write_to(&mut stream)
+                    .map_err(DaemonError::CommunicationError)
+            }
+            Request::List(instruction) => {
+                log::info!(event = "LIST", instruction);
+                let response = match supervisor.list_selected(&instruction) {
+                    Ok(details) => ListResponse::Success(details),
+                    Err(error) => {
+                        log::warning!(event = "LIST", instruction, error);
+                        ListResponse::Failure(error)
+                    }
+                };
+                log::debug!(event = "HANDLE", response);
+                response
+                    .write_to(&mut stream)
+                    .map_err(DaemonError::CommunicationError)
+            }
+            Request::Logs(instruction) => {
+                log::info!(event = "LOGS", instruction);
+                let result = supervisor.logs(&instruction, |output| {
+                    LogsResponse::Output(output.into())
+                        .write_to(&mut stream)
+                        .map_err(DaemonError::CommunicationError)
+                });
+                let response = match result {
+                    Ok(()) => LogsResponse::End,
+                    Err(DaemonError::CommunicationError(error)) => {
+                        // the client has gone away, so there's nobody to respond to
+                        log::debug!(event = "LOGS", instruction, error);
+                        return Ok(());
+                    }
+                    Err(error) => {
+                        log::warning!(event = "LOGS", instruction, error);
+                        LogsResponse::Failure(error)
+                    }
+                };
+                response
+                    .write_to(&mut stream)
+                    .map_err(DaemonError::CommunicationError)
+            }
+            Request::FollowLogs(instruction) => {
+                log::info!(event = "FOLLOW_LOGS", instruction);
+                let result = supervisor.follow_logs(&instruction, |output| {
+                    LogsResponse::Output(output.into())
+                        .write_to(&mut stream)
+                        .map_err(DaemonError::CommunicationError)
+                });
+                let response = match result {
+                    Ok(()) => LogsResponse::End,
+                    Err(DaemonError::CommunicationError(error)) => {
+                        // the client has gone away, so there's nobody to respond to
+                        log::debug!(event = "FOLLOW_LOGS", instruction, error);
+                        return Ok(());
+                    }
+                    Err(error) => {
+                        log::warning!(event = "FOLLOW_LOGS", instruction, error);
+                        LogsResponse::Failure(error)
+                    }
+                };
+                response
+                    .write_to(&mut stream)
+                    .map_err(DaemonError::CommunicationError)
+            }
             Request::Shutdown => {
                 stop_sender
                     .send(stream)
@@ -231,3 +566,170 @@ fn stop_requested(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::communication::{Start, Stop};
+    use crate::services::Service;
+    use crate::test_programs;
+    use crate::timing::DurationUnit;
+
+    use super::*;
+
+    #[test]
+    fn test_refuses_to_start_twice_on_the_same_socket() -> anyhow::Result<()> {
+        let socket_dir = tempfile::Builder::new()
+            .prefix("sandcastles-test")
+            .tempdir()?;
+        let socket_path = socket_dir.path().join("socket");
+        let _daemon = Daemon::start_on_socket(socket_path.clone())?;
+
+        let result = Daemon::start_on_socket(socket_path);
+
+        assert!(
+            matches!(result, Err(DaemonError::AlreadyRunning)),
+            "Expected the daemon to refuse to start."
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_refuses_to_start_if_a_daemon_answers_on_the_socket() -> anyhow::Result<()> {
+        let socket_dir = tempfile::Builder::new()
+            .prefix("sandcastles-test")
+            .tempdir()?;
+        let socket_path = socket_dir.path().join("socket");
+        let _daemon = Daemon::start_on_socket(socket_path.clone())?;
+        // without the lock file, we have to ask the other daemon
+        fs::remove_file(socket_dir.path().join("socket.lock"))?;
+
+        let result = Daemon::start_on_socket(socket_path);
+
+        assert!(
+            matches!(result, Err(DaemonError::AlreadyRunning)),
+            "Expected the daemon to refuse to start."
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_replaces_a_stale_socket() -> anyhow::Result<()> {
+        let socket_dir = tempfile::Builder::new()
+            .prefix("sandcastles-test")
+            .tempdir()?;
+        let socket_path = socket_dir.path().join("socket");
+        drop(UnixListener::bind(&socket_path)?);
+
+        let daemon = Daemon::start_on_socket(socket_path)?;
+        let mut client = Client::connect_to(daemon.socket())?;
+
+        client.ping()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_shuts_down_when_idle() -> anyhow::Result<()> {
+        let socket_dir = tempfile::Builder::new()
+            .prefix("sandcastles-test")
+            .tempdir()?;
+        let socket_path = socket_dir.path().join("socket");
+        let daemon = Daemon::start_on_socket_with_options(
+            socket_path.clone(),
+            DaemonOptions {
+                idle_timeout: Some(Duration::of(200, DurationUnit::Milliseconds)),
+                ..Default::default()
+            },
+        )?;
+
+        daemon.wait();
+        drop(daemon);
+
+        assert!(!socket_path.exists(), "The socket was not removed.");
+        Ok(())
+    }
+
+    #[test]
+    fn test_lets_a_new_daemon_start_as_soon_as_it_shuts_down_when_idle() -> anyhow::Result<()> {
+        let socket_dir = tempfile::Builder::new()
+            .prefix("sandcastles-test")
+            .tempdir()?;
+        let socket_path = socket_dir.path().join("socket");
+        let daemon = Daemon::start_on_socket_with_options(
+            socket_path.clone(),
+            DaemonOptions {
+                idle_timeout: Some(Duration::of(200, DurationUnit::Milliseconds)),
+                ..Default::default()
+            },
+        )?;
+        // a client keeps connecting, leaving just enough time for the daemon
+        // to become idle, so it may well connect while the daemon shuts down
+        let connecting = Arc::new(AtomicBool::new(true));
+        let client_thread = {
+            let socket_path = socket_path.clone();
+            let connecting = Arc::clone(&connecting);
+            thread::spawn(move || {
+                while connecting.load(Ordering::Relaxed) {
+                    let _ = Client::connect_and_ping(&socket_path);
+                    Duration::of(300, DurationUnit::Milliseconds).sleep();
+                }
+            })
+        };
+
+        daemon.wait();
+        let new_daemon = Daemon::start_on_socket(socket_path.clone())?;
+        drop(daemon);
+        connecting.store(false, Ordering::Relaxed);
+        client_thread.join().unwrap();
+
+        Client::connect_to(new_daemon.socket())?.ping()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_does_not_shut_down_while_services_are_running() -> anyhow::Result<()> {
+        let socket_dir = tempfile::Builder::new()
+            .prefix("sandcastles-test")
+            .tempdir()?;
+        let socket_path = socket_dir.path().join("socket");
+        let daemon = Daemon::start_on_socket_with_options(
+            socket_path.clone(),
+            DaemonOptions {
+                idle_timeout: Some(Duration::of(200, DurationUnit::Milliseconds)),
+                ..Default::default()
+            },
+        )?;
+        let name = Client::connect_to(daemon.socket())?.start(Start {
+            service: Service::Program(test_programs::waits_for_termination()),
+            ..Default::default()
+        })?;
+
+        Duration::of(500, DurationUnit::Milliseconds).sleep();
+        Client::connect_to(daemon.socket())?.stop(Stop { name })?;
+        daemon.wait();
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_does_not_shut_down_while_a_client_is_connected() -> anyhow::Result<()> {
+        let socket_dir = tempfile::Builder::new()
+            .prefix("sandcastles-test")
+            .tempdir()?;
+        let socket_path = socket_dir.path().join("socket");
+        let daemon = Daemon::start_on_socket_with_options(
+            socket_path.clone(),
+            DaemonOptions {
+                idle_timeout: Some(Duration::of(200, DurationUnit::Milliseconds)),
+                ..Default::default()
+            },
+        )?;
+        let mut client = Client::connect_to(daemon.socket())?;
+
+        Duration::of(500, DurationUnit::Milliseconds).sleep();
+        client.ping()?;
+        drop(client);
+        daemon.wait();
+
+        Ok(())
+    }
+}
@@ -1,7 +1,10 @@
+use std::path::PathBuf;
+
 use thiserror::Error;
 
 use crate::log::LoggableIoError;
 use crate::names::Name;
+use crate::wait::WaitFor;
 
 pub type ClientResult<A> = std::result::Result<A, ClientError>;
 
@@ -14,6 +17,12 @@ pub enum ClientError {
     CommunicationError(CommunicationError),
     #[error("daemon error: {0}")]
     DaemonError(DaemonError),
+    #[error("spawn daemon error: {0}")]
+    SpawnDaemonError(LoggableIoError),
+    #[error("timed out waiting for the daemon to start")]
+    SpawnDaemonTimeOut,
+    #[error("the daemon stopped before it was ready ({0}); see daemon.log")]
+    SpawnDaemonExited(String),
 }
 
 pub type DaemonResult<A> = std::result::Result<A, DaemonError>;
@@ -21,6 +30,10 @@ pub type DaemonResult<A> = std::result::Result<A, DaemonError>;
 #[derive(Debug, Clone, PartialEq, Eq, Error, serde::Serialize, serde::Deserialize)]
 #[serde(tag = "code", rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum DaemonError {
+    #[error("the daemon is already running")]
+    AlreadyRunning,
+    #[error("lock error: {0}")]
+    LockError(LoggableIoError),
     #[error("socket creation error: {0}")]
     SocketCreationError(LoggableIoError),
     #[error("socket configuration error: {0}")]
@@ -45,8 +58,92 @@ pub enum DaemonError {
         #[serde(flatten)]
         inner: LoggableIoError,
     },
-    #[error("timed out")]
-    TimeOut,
+    #[error("timed out waiting for {0}")]
+    TimeOut(WaitFor),
+    #[error("capture output error: {0}")]
+    CaptureOutputError(LoggableIoError),
+    #[error("read output error: {0}")]
+    ReadOutputError(LoggableIoError),
+    #[error("output not captured error")]
+    OutputNotCapturedError,
+    #[error("invalid pattern error: {0}")]
+    InvalidPatternError(InvalidPattern),
+    #[error("invalid URL error: {0}")]
+    InvalidUrlError(InvalidUrl),
+    #[error("nothing to wait for error: waiting for any condition needs at least one")]
+    NothingToWaitForError,
+    #[error("restore state error: {0}")]
+    RestoreStateError(LoggableIoError),
+    #[error("owner not running error")]
+    OwnerNotRunningError,
+    #[error("working directory error: {0}")]
+    WorkingDirectoryError(InvalidDirectory),
+    #[error("environment file error: {0}")]
+    EnvironmentFileError(InvalidEnvironmentFile),
+    #[error("Nix environment error: {0}")]
+    NixEnvironmentError(InvalidNixEnvironment),
+    #[error("shutting down error")]
+    ShuttingDownError,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct InvalidPattern {
+    pub pattern: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for InvalidPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: {}", self.pattern, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct InvalidUrl {
+    pub url: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for InvalidUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: {}", self.url, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct InvalidDirectory {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+impl std::fmt::Display for InvalidDirectory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: {}", self.path, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct InvalidEnvironmentFile {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+impl std::fmt::Display for InvalidEnvironmentFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: {}", self.path, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct InvalidNixEnvironment {
+    pub environment: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for InvalidNixEnvironment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: {}", self.environment, self.message)
+    }
 }
 
 pub type CommunicationResult<A> = Result<A, CommunicationError>;
@@ -0,0 +1,210 @@
+use std::collections::BTreeMap;
+
+/// Arbitrary `key=value` pairs attached to a service, so that services can be
+/// selected without knowing their names.
+pub type Labels = BTreeMap<String, String>;
+
+/// Parses a label in the format `key=value`.
+pub fn parse_label(s: &str) -> Result<(String, String), LabelError> {
+    match s.split_once('=') {
+        Some((key, value)) if is_valid_key(key) && is_valid_value(value) => {
+            Ok((key.to_owned(), value.to_owned()))
+        }
+        _ => Err(LabelError::InvalidLabel(s.to_owned())),
+    }
+}
+
+/// Selects services by their labels.
+///
+/// A selector is written as a comma-separated list of requirements, each of
+/// which is either `key=value` or `key!=value`. A service matches if it meets
+/// every requirement. The default selector has no requirements, and so matches
+/// everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Selector(Vec<Requirement>);
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Requirement {
+    Equals {
+        key: String,
+        value: String,
+    },
+    /// Also matches services without the label at all.
+    NotEquals {
+        key: String,
+        value: String,
+    },
+}
+
+impl Selector {
+    pub fn matches(&self, labels: &Labels) -> bool {
+        self.0.iter().all(|requirement| requirement.matches(labels))
+    }
+}
+
+impl Requirement {
+    fn matches(&self, labels: &Labels) -> bool {
+        match self {
+            Self::Equals { key, value } => labels.get(key) == Some(value),
+            Self::NotEquals { key, value } => labels.get(key) != Some(value),
+        }
+    }
+}
+
+impl std::fmt::Display for Selector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, requirement) in self.0.iter().enumerate() {
+            if index > 0 {
+                write!(f, ",")?;
+            }
+            match requirement {
+                Requirement::Equals { key, value } => write!(f, "{}={}", key, value)?,
+                Requirement::NotEquals { key, value } => write!(f, "{}!={}", key, value)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Selector {
+    type Err = LabelError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(LabelError::EmptySelector);
+        }
+        s.split(',')
+            .map(|requirement| {
+                let invalid = || LabelError::InvalidSelector(s.to_owned());
+                let (key, value, not) = match requirement.split_once("!=") {
+                    Some((key, value)) => (key, value, true),
+                    None => {
+                        let (key, value) = requirement.split_once('=').ok_or_else(invalid)?;
+                        (key, value, false)
+                    }
+                };
+                if !is_valid_key(key) || !is_valid_value(value) {
+                    return Err(invalid());
+                }
+                let (key, value) = (key.to_owned(), value.to_owned());
+                Ok(if not {
+                    Requirement::NotEquals { key, value }
+                } else {
+                    Requirement::Equals { key, value }
+                })
+            })
+            .collect::<Result<Vec<Requirement>, LabelError>>()
+            .map(Self)
+    }
+}
+
+// Keys and values can contain anything except the characters we use to
+// separate them, and keys cannot be empty.
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && is_valid_value(key)
+}
+
+fn is_valid_value(value: &str) -> bool {
+    !value.contains(|c: char| c == '=' || c == ',' || c == '!' || c.is_whitespace())
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LabelError {
+    InvalidLabel(String),
+    EmptySelector,
+    InvalidSelector(String),
+}
+
+impl std::fmt::Display for LabelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            LabelError::InvalidLabel(label) => write!(f, "invalid label: {:?}, the label must be in the format `key=value`", label),
+            LabelError::EmptySelector => write!(f, "a selector cannot be empty"),
+            LabelError::InvalidSelector(selector) => write!(f, "invalid selector: {:?}, the selector must be a comma-separated list of `key=value` or `key!=value`", selector),
+        }
+    }
+}
+
+impl std::error::Error for LabelError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> Labels {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parses_a_label() {
+        let label = parse_label("project=foo");
+
+        assert_eq!(label, Ok(("project".to_owned(), "foo".to_owned())));
+    }
+
+    #[test]
+    fn test_rejects_a_label_without_a_value() {
+        let label = parse_label("project");
+
+        assert_eq!(label, Err(LabelError::InvalidLabel("project".to_owned())));
+    }
+
+    #[test]
+    fn test_parses_a_selector() -> anyhow::Result<()> {
+        let selector = "project=foo,suite!=slow".parse::<Selector>()?;
+
+        assert_eq!(
+            selector,
+            Selector(vec![
+                Requirement::Equals {
+                    key: "project".to_owned(),
+                    value: "foo".to_owned()
+                },
+                Requirement::NotEquals {
+                    key: "suite".to_owned(),
+                    value: "slow".to_owned()
+                },
+            ])
+        );
+        assert_eq!(selector.to_string(), "project=foo,suite!=slow");
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_an_empty_selector() {
+        let selector = "".parse::<Selector>();
+
+        assert_eq!(selector, Err(LabelError::EmptySelector));
+    }
+
+    #[test]
+    fn test_rejects_an_invalid_selector() {
+        let selector = "project=foo,suite".parse::<Selector>();
+
+        assert_eq!(
+            selector,
+            Err(LabelError::InvalidSelector("project=foo,suite".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_selects_services_that_meet_every_requirement() -> anyhow::Result<()> {
+        let selector = "project=foo,suite!=slow".parse::<Selector>()?;
+
+        assert!(selector.matches(&labels(&[("project", "foo")])));
+        assert!(selector.matches(&labels(&[("project", "foo"), ("suite", "fast")])));
+        assert!(!selector.matches(&labels(&[("project", "foo"), ("suite", "slow")])));
+        assert!(!selector.matches(&labels(&[("project", "bar")])));
+        assert!(!selector.matches(&labels(&[])));
+        Ok(())
+    }
+
+    #[test]
+    fn test_selects_everything_by_default() {
+        assert!(Selector::default().matches(&labels(&[])));
+        assert!(Selector::default().matches(&labels(&[("project", "foo")])));
+    }
+}
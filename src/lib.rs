@@ -3,7 +3,10 @@ pub mod client;
 pub mod communication;
 pub mod daemon;
 pub mod error;
+pub mod labels;
+pub mod output;
 pub mod ports;
+pub mod restart;
 pub mod services;
 pub mod supervisor;
 pub mod timing;
@@ -11,6 +14,7 @@ pub mod wait;
 
 mod log;
 mod names;
+mod processes;
 
 mod test_helpers;
 mod test_programs;
@@ -18,9 +22,12 @@ mod test_services;
 
 pub use client::Client;
 pub use communication::*;
-pub use daemon::Daemon;
+pub use daemon::{Daemon, DaemonOptions};
+pub use labels::{Labels, Selector};
 pub use names::{Name, NameError};
+pub use output::Stream;
 pub use ports::Port;
+pub use restart::RestartPolicy;
 pub use services::*;
-pub use supervisor::Supervisor;
+pub use supervisor::{Supervisor, SupervisorOptions};
 pub use wait::WaitFor;
@@ -1,18 +1,25 @@
+use std::collections::BTreeMap;
 use std::env;
 use std::fs;
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
 use std::path::PathBuf;
-use std::process::ExitCode;
+use std::process::{self, ExitCode};
 use std::sync::Arc;
+use std::thread;
 
 use clap::Parser;
 use signal_hook::consts::signal;
 
+use sandcastles::timing::Duration;
 use sandcastles::*;
 
 mod args {
     use std::path::PathBuf;
 
-    use sandcastles::{Argument, Name};
+    use sandcastles::labels::parse_label;
+    use sandcastles::timing::Duration;
+    use sandcastles::{Argument, Name, Port, RestartPolicy, Selector, WaitFor};
 
     #[derive(Debug, clap::Parser)]
     #[command(author, version, about, long_about = None)]
@@ -24,8 +31,19 @@ mod args {
     }
 
     #[derive(Debug, clap::Subcommand)]
+    // this is parsed once, so the size doesn't matter
+    #[allow(clippy::large_enum_variant)]
     pub enum Command {
-        Daemon,
+        Daemon {
+            #[arg(long = "start-timeout", default_value_t = Duration::START_TIMEOUT)]
+            start_timeout: Duration,
+            /// Stay attached to the terminal, logging to stderr.
+            #[arg(long = "foreground")]
+            foreground: bool,
+            /// Shut down once there has been nothing to do for this long.
+            #[arg(long = "idle-timeout")]
+            idle_timeout: Option<Duration>,
+        },
         Start {
             #[arg(long = "name")]
             name: Option<Name>,
@@ -33,13 +51,158 @@ mod args {
             arguments: Vec<Argument>,
             #[arg(long = "env", value_parser = parse_env)]
             environment: Vec<(Argument, Argument)>,
+            /// Pass on an environment variable from the current environment.
+            /// `PATH` is always passed on. Unlike `--env`, these can be
+            /// overridden by `.env` files.
+            #[arg(long = "pass-env")]
+            pass_environment: Vec<String>,
+            /// Start with an empty environment, rather than the daemon's.
+            #[arg(long = "clean-env")]
+            clean_environment: bool,
+            /// Keep a variable from the daemon's environment, starting with an
+            /// otherwise-empty environment.
+            #[arg(long = "allow-env")]
+            allow_environment: Vec<Argument>,
+            /// Load variables from a `.env` file, relative to the working
+            /// directory. Variables set with `--env` take precedence.
+            #[arg(long = "env-file")]
+            environment_files: Vec<PathBuf>,
+            /// Where to run the command. Defaults to the current directory.
+            #[arg(long = "working-directory")]
+            working_directory: Option<PathBuf>,
+            /// Run the command in the development environment of a flake, such
+            /// as `.#dev`, as if by `nix develop`.
+            #[arg(long = "nix-flake", conflicts_with = "nix_file")]
+            nix_flake: Option<String>,
+            /// Run the command in the environment defined by a Nix file, such
+            /// as `shell.nix`, as if by `nix-shell`.
+            #[arg(long = "nix-file")]
+            nix_file: Option<PathBuf>,
+            /// How long to wait for the service to be ready. Defaults to the
+            /// daemon's start timeout.
+            #[arg(long = "timeout")]
+            timeout: Option<Duration>,
+            #[command(flatten)]
+            restart: RestartArguments,
+            /// Stop the service when this process exits. Defaults to the
+            /// parent process, which is usually the shell.
+            #[arg(long = "owner", conflicts_with = "no_owner")]
+            owner: Option<u32>,
+            /// Keep the service running until it is explicitly stopped.
+            #[arg(long = "no-owner")]
+            no_owner: bool,
+            /// Add the service to a group, so it can be stopped with the rest.
+            #[arg(long = "group")]
+            group: Option<Name>,
+            /// Label the service, so it can be selected later.
+            #[arg(long = "label", value_parser = parse_label)]
+            labels: Vec<(String, String)>,
         },
         Stop {
+            #[arg(required_unless_present_any = ["group", "selector"])]
+            name: Option<Name>,
+            /// Stop every service in the group, most recently started first.
+            #[arg(long = "group", conflicts_with = "name")]
+            group: Option<Name>,
+            /// Stop every service matching the selector, such as
+            /// `project=foo,suite!=slow`, most recently started first.
+            #[arg(long = "selector", short = 'l', conflicts_with_all = ["name", "group"])]
+            selector: Option<Selector>,
+        },
+        List {
+            /// Only list the services matching the selector.
+            #[arg(long = "selector", short = 'l')]
+            selector: Option<Selector>,
+            #[arg(long = "format", value_enum, default_value_t = ListFormat::Text)]
+            format: ListFormat,
+        },
+        Logs {
             name: Name,
+            #[arg(long = "stream", value_enum, default_value_t = Stream::Stdout)]
+            stream: Stream,
+            #[arg(long = "tail")]
+            tail: Option<usize>,
+            #[arg(long = "follow", short = 'f')]
+            follow: bool,
+        },
+        /// Starts some services, runs a command, and then stops the services.
+        Run {
+            #[arg(long = "service", value_parser = parse_service)]
+            services: Vec<(Name, String)>,
+            /// Wait for a service to be ready before going on, with
+            /// `NAME=port:PORT`, `NAME=http:URL`, `NAME=stdout:PATTERN` or
+            /// `NAME=stderr:PATTERN`. Otherwise, we only wait a moment.
+            #[arg(long = "wait", value_parser = parse_wait)]
+            waits: Vec<(Name, WaitFor)>,
+            /// How long to wait for each service to be ready. Defaults to the
+            /// daemon's start timeout.
+            #[arg(long = "timeout")]
+            timeout: Option<Duration>,
+            command: Argument,
+            arguments: Vec<Argument>,
         },
         Shutdown,
     }
 
+    #[derive(Debug, clap::Args)]
+    pub struct RestartArguments {
+        #[arg(long = "restart", value_enum, default_value_t = Restart::Never)]
+        restart: Restart,
+        #[arg(long = "max-retries")]
+        max_retries: Option<u32>,
+        #[arg(long = "backoff", default_value_t = Duration::RESTART_BACKOFF)]
+        backoff: Duration,
+    }
+
+    #[derive(Debug, Clone, Copy, clap::ValueEnum)]
+    enum Restart {
+        Never,
+        OnFailure,
+        Always,
+    }
+
+    impl From<RestartArguments> for RestartPolicy {
+        fn from(arguments: RestartArguments) -> Self {
+            let RestartArguments {
+                restart,
+                max_retries,
+                backoff,
+            } = arguments;
+            match restart {
+                Restart::Never => Self::Never,
+                Restart::OnFailure => Self::OnFailure {
+                    max_retries,
+                    backoff,
+                },
+                Restart::Always => Self::Always {
+                    max_retries,
+                    backoff,
+                },
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, clap::ValueEnum)]
+    pub enum Stream {
+        Stdout,
+        Stderr,
+    }
+
+    impl From<Stream> for sandcastles::Stream {
+        fn from(stream: Stream) -> Self {
+            match stream {
+                Stream::Stdout => Self::Stdout,
+                Stream::Stderr => Self::Stderr,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, clap::ValueEnum)]
+    pub enum ListFormat {
+        Text,
+        Json,
+    }
+
     fn parse_env(arg: &str) -> Result<(Argument, Argument), &'static str> {
         if let [name, value] = arg.splitn(2, '=').collect::<Vec<&str>>()[..] {
             Ok((name.into(), value.into()))
@@ -47,17 +210,113 @@ mod args {
             Err("must be in the format `NAME=VALUE`")
         }
     }
+
+    fn parse_service(arg: &str) -> Result<(Name, String), String> {
+        if let [name, command] = arg.splitn(2, '=').collect::<Vec<&str>>()[..] {
+            let name = name.parse::<Name>().map_err(|error| error.to_string())?;
+            Ok((name, command.to_owned()))
+        } else {
+            Err("must be in the format `NAME=COMMAND`".to_owned())
+        }
+    }
+
+    fn parse_wait(arg: &str) -> Result<(Name, WaitFor), String> {
+        let Some((name, condition)) = arg.split_once('=') else {
+            return Err("must be in the format `NAME=CONDITION`".to_owned());
+        };
+        let name = name.parse::<Name>().map_err(|error| error.to_string())?;
+        let condition = match condition.split_once(':') {
+            Some(("port", port)) => WaitFor::Port {
+                port: Port(
+                    port.parse()
+                        .map_err(|_| format!("invalid port: {}", port))?,
+                ),
+            },
+            Some(("http", url)) => WaitFor::Http {
+                url: url.to_owned(),
+                expected_status: None,
+                body_contains: None,
+            },
+            Some(("stdout", pattern)) => WaitFor::Output {
+                stream: sandcastles::Stream::Stdout,
+                pattern: pattern.to_owned(),
+            },
+            Some(("stderr", pattern)) => WaitFor::Output {
+                stream: sandcastles::Stream::Stderr,
+                pattern: pattern.to_owned(),
+            },
+            _ => {
+                return Err(
+                    "the condition must be `port:PORT`, `http:URL`, `stdout:PATTERN` or `stderr:PATTERN`"
+                        .to_owned(),
+                );
+            }
+        };
+        Ok((name, condition))
+    }
 }
 
 fn main() -> anyhow::Result<ExitCode> {
     let args = args::Arguments::parse();
     let socket_path = args.socket_path.unwrap_or_else(default_socket_path);
     match args.command {
-        args::Command::Daemon => {
+        args::Command::Daemon {
+            start_timeout,
+            foreground: false,
+            idle_timeout,
+        } => {
+            if Client::connect_and_ping(&socket_path).is_ok() {
+                return Err(sandcastles::error::DaemonError::AlreadyRunning.into());
+            }
+            let mut arguments = vec!["--start-timeout".into(), start_timeout.to_string().into()];
+            if let Some(idle_timeout) = idle_timeout {
+                arguments.extend(["--idle-timeout".into(), idle_timeout.to_string().into()]);
+            }
+            let daemon =
+                sandcastles::daemon::spawn(&env::current_exe()?, &socket_path, &arguments)?;
+            let process_id = daemon.id();
+            Client::connect_when_ready(&socket_path, daemon)?;
+            println!("socket: {}", socket_path.display());
+            println!("pid: {}", process_id);
+            Ok(ExitCode::SUCCESS)
+        }
+        args::Command::Daemon {
+            start_timeout,
+            foreground: true,
+            idle_timeout,
+        } => {
             if let Some(socket_dir) = socket_path.parent() {
                 fs::create_dir_all(socket_dir)?;
             }
-            let daemon = Arc::new(Daemon::start_on_socket(socket_path)?);
+            // named after the socket, so that daemons with sockets in the
+            // same directory don't share one
+            let mut pid_file = socket_path.as_os_str().to_owned();
+            pid_file.push(".pid");
+            let daemon = match Daemon::start_on_socket_with_options(
+                socket_path,
+                DaemonOptions {
+                    supervisor: SupervisorOptions {
+                        start_timeout,
+                        ..Default::default()
+                    },
+                    idle_timeout,
+                },
+            ) {
+                Ok(daemon) => Arc::new(daemon),
+                // whoever started us can wait for the other daemon instead
+                Err(error @ sandcastles::error::DaemonError::AlreadyRunning) => {
+                    eprintln!("Error: {}", error);
+                    return Ok(ExitCode::from(
+                        sandcastles::daemon::ALREADY_RUNNING_EXIT_CODE,
+                    ));
+                }
+                Err(error) => {
+                    return Err(error.into());
+                }
+            };
+            // we only write the PID file once we hold the lock, so that we
+            // don't overwrite another daemon's
+            fs::write(&pid_file, format!("{}\n", std::process::id()))?;
             unsafe {
                 for signal in [signal::SIGINT, signal::SIGQUIT, signal::SIGTERM] {
                     let daemon_for_signal = Arc::downgrade(&daemon);
@@ -69,6 +328,10 @@ fn main() -> anyhow::Result<ExitCode> {
                 }
             }
             daemon.wait();
+            // a new daemon may already have replaced us, along with the file
+            if fs::read_to_string(&pid_file).ok() == Some(format!("{}\n", std::process::id())) {
+                fs::remove_file(pid_file)?;
+            }
             Ok(ExitCode::SUCCESS)
         }
         args::Command::Start {
@@ -76,25 +339,172 @@ fn main() -> anyhow::Result<ExitCode> {
             command,
             arguments,
             environment,
+            pass_environment,
+            clean_environment,
+            allow_environment,
+            environment_files,
+            working_directory,
+            nix_flake,
+            nix_file,
+            timeout,
+            restart,
+            owner,
+            no_owner,
+            group,
+            labels,
         } => {
-            let mut client = Client::connect_to(&socket_path)?;
+            let owner = if no_owner {
+                None
+            } else {
+                Some(owner.unwrap_or_else(|| nix::unistd::getppid().as_raw().unsigned_abs()))
+            };
+            let program = Program {
+                command,
+                arguments,
+                environment: environment.into_iter().collect(),
+                base_environment: client_environment(pass_environment),
+                environment_policy: match (clean_environment, allow_environment) {
+                    (false, allowed) if allowed.is_empty() => EnvironmentPolicy::Inherit,
+                    (true, allowed) if allowed.is_empty() => EnvironmentPolicy::Clean,
+                    (_, allowed) => EnvironmentPolicy::Allowlist(allowed),
+                },
+                environment_files,
+                // relative paths are relative to us, not the daemon
+                working_directory: Some(match working_directory {
+                    Some(directory) => env::current_dir()?.join(directory),
+                    None => env::current_dir()?,
+                }),
+            };
+            let service = match (nix_flake, nix_file) {
+                (Some(reference), _) => Service::Nix(NixProgram {
+                    environment: NixEnvironment::Flake(reference),
+                    program,
+                }),
+                (None, Some(path)) => Service::Nix(NixProgram {
+                    environment: NixEnvironment::File(path),
+                    program,
+                }),
+                (None, None) => Service::Program(program),
+            };
+            let mut client = Client::connect_or_spawn(&socket_path)?;
             let name = client.start(Start {
                 name,
-                service: Service::Program(Program {
-                    command,
-                    arguments,
-                    environment: environment.into_iter().collect(),
-                }),
+                service,
                 wait: WaitFor::AMoment,
+                timeout,
+                owner,
+                group,
+                labels: labels.into_iter().collect(),
+                restart: restart.into(),
             })?;
             println!("{}", name);
             Ok(ExitCode::SUCCESS)
         }
-        args::Command::Stop { name } => {
+        args::Command::Stop {
+            name: Some(name), ..
+        } => {
             let mut client = Client::connect_to(&socket_path)?;
             let exit_status = client.stop(Stop { name })?;
             Ok(exit_status.into())
         }
+        args::Command::Stop {
+            group: Some(group), ..
+        } => {
+            let mut client = Client::connect_to(&socket_path)?;
+            print_stopped(client.stop_group(StopGroup { group })?);
+            Ok(ExitCode::SUCCESS)
+        }
+        args::Command::Stop {
+            selector: Some(selector),
+            ..
+        } => {
+            let mut client = Client::connect_to(&socket_path)?;
+            print_stopped(client.stop_selected(StopSelected { selector })?);
+            Ok(ExitCode::SUCCESS)
+        }
+        args::Command::Stop { .. } => {
+            unreachable!("A name, a group, or a selector is required.")
+        }
+        args::Command::List { selector, format } => {
+            let mut client = Client::connect_to(&socket_path)?;
+            let details = client.list_selected(List {
+                selector: selector.unwrap_or_default(),
+            })?;
+            match format {
+                args::ListFormat::Text => {
+                    for service in details {
+                        println!(
+                            "{}\t{}\t{}\t{}\t{}",
+                            service.name,
+                            service.process_id,
+                            describe_status(&service),
+                            service
+                                .start_time
+                                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                            describe(&service.service),
+                        );
+                    }
+                }
+                args::ListFormat::Json => {
+                    println!("{}", serde_json::to_string_pretty(&details)?);
+                }
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        args::Command::Logs {
+            name,
+            stream,
+            tail,
+            follow,
+        } => {
+            let mut client = Client::connect_to(&socket_path)?;
+            let instruction = Logs {
+                name,
+                stream: stream.into(),
+                tail,
+            };
+            let mut stdout = io::stdout();
+            let outputs: Box<dyn Iterator<Item = sandcastles::error::ClientResult<Vec<u8>>>> =
+                if follow {
+                    Box::new(client.follow_logs(instruction)?)
+                } else {
+                    Box::new(client.logs(instruction)?)
+                };
+            for output in outputs {
+                stdout.write_all(&output?)?;
+                stdout.flush()?;
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        args::Command::Run {
+            services,
+            waits,
+            timeout,
+            command,
+            arguments,
+        } => {
+            let mut client = Client::connect_or_spawn(&socket_path)?;
+            let mut started = Vec::new();
+            let result = start_and_run(
+                &mut client,
+                services,
+                waits,
+                timeout,
+                &mut started,
+                command,
+                arguments,
+            );
+            // we stop the services whatever happened, in reverse order
+            let mut stop_result = Ok(());
+            for name in started.into_iter().rev() {
+                if let Err(error) = client.stop(Stop { name }) {
+                    stop_result = stop_result.and(Err(error));
+                }
+            }
+            let exit_code = result?;
+            stop_result?;
+            Ok(exit_code)
+        }
         args::Command::Shutdown => {
             let mut client = Client::connect_to(&socket_path)?;
             client.shutdown()?;
@@ -103,6 +513,142 @@ fn main() -> anyhow::Result<ExitCode> {
     }
 }
 
+fn start_and_run(
+    client: &mut Client,
+    services: Vec<(Name, String)>,
+    waits: Vec<(Name, WaitFor)>,
+    timeout: Option<Duration>,
+    started: &mut Vec<Name>,
+    command: Argument,
+    arguments: Vec<Argument>,
+) -> anyhow::Result<ExitCode> {
+    let mut waits = waits.into_iter().collect::<BTreeMap<Name, WaitFor>>();
+    if let Some(name) = waits
+        .keys()
+        .find(|name| !services.iter().any(|(service, _)| service == *name))
+    {
+        anyhow::bail!("there is no service named {} to wait for", name);
+    }
+    // if we're asked to stop, we pass that on to the command, and then clean
+    // up once it's finished; we listen from the start, so that we can still
+    // clean up if we're asked to stop while starting the services
+    let mut signals = signal_hook::iterator::Signals::new([
+        signal::SIGINT,
+        signal::SIGQUIT,
+        signal::SIGTERM,
+        signal::SIGHUP,
+    ])?;
+    for (name, service_command) in services {
+        client.start(Start {
+            name: Some(name.clone()),
+            service: Service::Program(Program {
+                command: "sh".into(),
+                arguments: vec!["-c".into(), service_command.into()],
+                base_environment: client_environment(Vec::new()),
+                working_directory: Some(env::current_dir()?),
+                ..Default::default()
+            }),
+            wait: waits.remove(&name).unwrap_or_default(),
+            timeout,
+            // if we're killed before we can clean up, the daemon does it for us
+            owner: Some(process::id()),
+            group: None,
+            labels: Default::default(),
+            restart: RestartPolicy::Never,
+        })?;
+        started.push(name);
+        if let Some(signal) = signals.pending().next() {
+            return Ok(ExitStatus::ExitedWithSignal(signal.try_into()?).into());
+        }
+    }
+
+    let mut child = process::Command::new(command).args(arguments).spawn()?;
+    let signals_handle = signals.handle();
+    let child_process_id = nix::unistd::Pid::from_raw(child.id().try_into()?);
+    // the command shares our process group, so if that's in the foreground,
+    // the terminal sends keyboard signals to the command as well as to us,
+    // and passing them on would deliver them twice
+    let in_foreground = nix::unistd::tcgetpgrp(io::stdin().as_raw_fd())
+        .map_or(false, |group| group == nix::unistd::getpgrp());
+    let forwarder = thread::spawn(move || {
+        for signal in signals.forever() {
+            if in_foreground && (signal == signal::SIGINT || signal == signal::SIGQUIT) {
+                continue;
+            }
+            if let Ok(signal) = nix::sys::signal::Signal::try_from(signal) {
+                let _ = nix::sys::signal::kill(child_process_id, signal);
+            }
+        }
+    });
+    let exit_status = child.wait();
+    signals_handle.close();
+    forwarder
+        .join()
+        .expect("Failed to stop forwarding signals.");
+    Ok(ExitStatus::from(exit_status?).into())
+}
+
+// The environment passed on from this client: our `PATH`, so that the daemon
+// finds the same tools we would, along with any variables we were asked to
+// pass on. `.env` files can still override or extend these.
+fn client_environment(pass: Vec<String>) -> Environment {
+    std::iter::once("PATH".to_owned())
+        .chain(pass)
+        .filter_map(|name| env::var_os(&name).map(|value| (name.into(), value.into())))
+        .collect()
+}
+
+fn describe(service: &Service) -> String {
+    match service {
+        Service::Program(program) => describe_program(program),
+        Service::Nix(nix_program) => format!(
+            "{} (in {})",
+            describe_program(&nix_program.program),
+            nix_program.environment
+        ),
+    }
+}
+
+fn describe_program(program: &Program) -> String {
+    std::iter::once(&program.command)
+        .chain(program.arguments.iter())
+        .map(|argument| argument.as_ref().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn describe_status(service: &ServiceDetails) -> String {
+    let status = match (service.state, &service.last_exit) {
+        (ServiceState::Starting, _) => "starting".to_owned(),
+        (ServiceState::Running, _) => "running".to_owned(),
+        (ServiceState::Restarting, None) => "restarting".to_owned(),
+        (ServiceState::Restarting, Some(exit)) => {
+            format!("{}, restarting", describe_exit_status(&exit.status))
+        }
+        (ServiceState::Exited, None) => "stopped".to_owned(),
+        (ServiceState::Exited, Some(exit)) => describe_exit_status(&exit.status),
+    };
+    match service.restarts {
+        0 => status,
+        1 => format!("{} (restarted once)", status),
+        restarts => format!("{} (restarted {} times)", status, restarts),
+    }
+}
+
+fn print_stopped(exit_statuses: Vec<(Name, ExitStatus)>) {
+    for (name, exit_status) in exit_statuses {
+        println!("{}\t{}", name, describe_exit_status(&exit_status));
+    }
+}
+
+fn describe_exit_status(exit_status: &ExitStatus) -> String {
+    match exit_status {
+        ExitStatus::None => "exited".to_owned(),
+        ExitStatus::ExitedWithCode(code) => format!("exited with code {}", code),
+        ExitStatus::ExitedWithSignal(signal) => format!("exited with signal {}", signal),
+    }
+}
+
 fn default_socket_path() -> PathBuf {
     env::var_os("XDG_STATE_HOME")
         .map(PathBuf::from)
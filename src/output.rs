@@ -0,0 +1,292 @@
+use std::fs;
+use std::io::{self, Read, Seek};
+use std::path::{Path, PathBuf};
+
+use crate::names::Name;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl std::fmt::Display for Stream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stream::Stdout => write!(f, "stdout"),
+            Stream::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+/// Where a service's output ends up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Output {
+    /// The output is passed straight through to our own output streams.
+    Inherit,
+    /// The output is captured in a file per stream.
+    Capture { stdout: PathBuf, stderr: PathBuf },
+}
+
+impl Output {
+    pub(crate) fn in_directory(directory: &Path, name: &Name) -> Self {
+        Self::Capture {
+            stdout: directory.join(format!("{}.{}.log", name, Stream::Stdout)),
+            stderr: directory.join(format!("{}.{}.log", name, Stream::Stderr)),
+        }
+    }
+
+    pub(crate) fn path(&self, stream: Stream) -> Option<&Path> {
+        match (self, stream) {
+            (Self::Inherit, _) => None,
+            (Self::Capture { stdout, .. }, Stream::Stdout) => Some(stdout),
+            (Self::Capture { stderr, .. }, Stream::Stderr) => Some(stderr),
+        }
+    }
+
+    /// Removes any output left over from a previous service with the same name.
+    pub(crate) fn clear(&self) -> io::Result<()> {
+        for stream in [Stream::Stdout, Stream::Stderr] {
+            if let Some(path) = self.path(stream) {
+                match fs::remove_file(path) {
+                    Ok(()) => {}
+                    Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+                    Err(error) => {
+                        return Err(error);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens the file for the given stream, ready to be handed to a process.
+    ///
+    /// Output is appended, so that if the service is restarted, we keep the
+    /// output from previous runs.
+    pub(crate) fn open(&self, stream: Stream) -> io::Result<Option<fs::File>> {
+        self.path(stream)
+            .map(|path| {
+                if let Some(directory) = path.parent() {
+                    fs::create_dir_all(directory)?;
+                }
+                fs::OpenOptions::new().create(true).append(true).open(path)
+            })
+            .transpose()
+    }
+}
+
+// How much output we read at once, so that large files aren't read into
+// memory all at once.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Reads the output captured in the given file, a chunk at a time.
+///
+/// If `lines` is provided, only that many lines are read, from the end.
+pub(crate) fn read(path: &Path, lines: Option<usize>) -> io::Result<Chunks> {
+    let mut file = fs::File::open(path)?;
+    if let Some(lines) = lines {
+        let start = tail_start(&mut file, lines)?;
+        file.seek(io::SeekFrom::Start(start))?;
+    }
+    Ok(Chunks { file })
+}
+
+/// The output in a file, read a chunk at a time.
+pub(crate) struct Chunks {
+    file: fs::File,
+}
+
+impl Iterator for Chunks {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+        match (&mut self.file)
+            .take(CHUNK_SIZE as u64)
+            .read_to_end(&mut chunk)
+        {
+            Ok(0) => None,
+            Ok(_) => Some(Ok(chunk)),
+            Err(error) => Some(Err(error)),
+        }
+    }
+}
+
+/// Follows the output captured in a file as it is written, like `tail -f`.
+pub(crate) struct Follower {
+    chunks: Chunks,
+}
+
+impl Follower {
+    /// Opens the file, ready to read the output so far.
+    ///
+    /// If `lines` is provided, only that many lines of the existing output are
+    /// read, from the end.
+    pub(crate) fn open(path: &Path, lines: Option<usize>) -> io::Result<Self> {
+        Ok(Self {
+            chunks: read(path, lines)?,
+        })
+    }
+
+    /// Opens the file, ignoring the output so far.
+    pub(crate) fn open_at_end(path: &Path) -> io::Result<Self> {
+        let mut file = fs::File::open(path)?;
+        file.seek(io::SeekFrom::End(0))?;
+        Ok(Self {
+            chunks: Chunks { file },
+        })
+    }
+
+    /// Reads any output written since we last read, a chunk at a time.
+    pub(crate) fn read_more(&mut self) -> &mut Chunks {
+        &mut self.chunks
+    }
+}
+
+// Finds where the last `lines` lines of the file start, like `tail`, but
+// reading backwards from the end rather than reading the whole file.
+fn tail_start(file: &mut fs::File, lines: usize) -> io::Result<u64> {
+    let length = file.seek(io::SeekFrom::End(0))?;
+    if lines == 0 {
+        return Ok(length);
+    }
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut newlines = 0;
+    let mut end = length;
+    while end > 0 {
+        let start = end.saturating_sub(CHUNK_SIZE as u64);
+        let chunk = &mut buffer[..(end - start) as usize];
+        file.seek(io::SeekFrom::Start(start))?;
+        file.read_exact(chunk)?;
+        // ignore the trailing newline, if there is one
+        let chunk = if end == length {
+            chunk.strip_suffix(b"\n").unwrap_or(chunk)
+        } else {
+            chunk
+        };
+        for (index, _) in chunk
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, byte)| **byte == b'\n')
+        {
+            newlines += 1;
+            if newlines == lines {
+                return Ok(start + index as u64 + 1);
+            }
+        }
+        end = start;
+    }
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn test_reads_a_file_in_chunks() -> anyhow::Result<()> {
+        let directory = tempfile::tempdir()?;
+        let path = directory.path().join("output.log");
+        let contents = "line\n".repeat(CHUNK_SIZE);
+        fs::write(&path, &contents)?;
+
+        let chunks = read(&path, None)?.collect::<io::Result<Vec<Vec<u8>>>>()?;
+
+        assert_eq!(chunks.len(), 5);
+        assert_eq!(chunks.concat(), contents.as_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reads_the_last_lines_of_a_file() -> anyhow::Result<()> {
+        let directory = tempfile::tempdir()?;
+        let path = directory.path().join("output.log");
+        let contents = (0..CHUNK_SIZE)
+            .map(|line| format!("{}\n", line))
+            .collect::<String>();
+        fs::write(&path, &contents)?;
+
+        let everything =
+            read(&path, Some(CHUNK_SIZE + 1))?.collect::<io::Result<Vec<Vec<u8>>>>()?;
+        let last = read(&path, Some(2))?.collect::<io::Result<Vec<Vec<u8>>>>()?;
+        let nothing = read(&path, Some(0))?.collect::<io::Result<Vec<Vec<u8>>>>()?;
+
+        assert_eq!(everything.concat(), contents.as_bytes());
+        assert_eq!(
+            last.concat(),
+            format!("{}\n{}\n", CHUNK_SIZE - 2, CHUNK_SIZE - 1).as_bytes()
+        );
+        assert_eq!(nothing.concat(), b"");
+        Ok(())
+    }
+
+    #[test]
+    fn test_reads_an_incomplete_last_line() -> anyhow::Result<()> {
+        let directory = tempfile::tempdir()?;
+        let path = directory.path().join("output.log");
+        fs::write(&path, "one\ntwo\nthree")?;
+
+        let last = read(&path, Some(1))?.collect::<io::Result<Vec<Vec<u8>>>>()?;
+
+        assert_eq!(last.concat(), b"three");
+        Ok(())
+    }
+
+    #[test]
+    fn test_follows_a_file() -> anyhow::Result<()> {
+        let directory = tempfile::tempdir()?;
+        let path = directory.path().join("output.log");
+        fs::write(&path, "one\ntwo\n")?;
+
+        let mut follower = Follower::open(&path, Some(1))?;
+        let initial = follower.read_more().collect::<io::Result<Vec<Vec<u8>>>>()?;
+        let nothing = follower.read_more().collect::<io::Result<Vec<Vec<u8>>>>()?;
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)?
+            .write_all(b"three\n")?;
+        let more = follower.read_more().collect::<io::Result<Vec<Vec<u8>>>>()?;
+
+        assert_eq!(initial.concat(), b"two\n");
+        assert_eq!(nothing.concat(), b"");
+        assert_eq!(more.concat(), b"three\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_follows_a_file_from_the_end() -> anyhow::Result<()> {
+        let directory = tempfile::tempdir()?;
+        let path = directory.path().join("output.log");
+        fs::write(&path, "one\ntwo\n")?;
+
+        let mut follower = Follower::open_at_end(&path)?;
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)?
+            .write_all(b"three\n")?;
+        let more = follower.read_more().collect::<io::Result<Vec<Vec<u8>>>>()?;
+
+        assert_eq!(more.concat(), b"three\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_names_files_after_the_service_and_stream() -> anyhow::Result<()> {
+        let output = Output::in_directory(Path::new("/logs"), &"thing".parse()?);
+
+        assert_eq!(
+            output.path(Stream::Stdout),
+            Some(Path::new("/logs/thing.stdout.log"))
+        );
+        assert_eq!(
+            output.path(Stream::Stderr),
+            Some(Path::new("/logs/thing.stderr.log"))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+use std::fs;
+
+/// Finds the time at which a process started, in clock ticks since boot.
+///
+/// Along with the process ID, this identifies a process uniquely, even if the
+/// process ID is later reused.
+///
+/// Returns `None` if the process is not running, including if it has exited
+/// but not yet been reaped.
+pub(crate) fn start_time(process_id: u32) -> Option<u64> {
+    let stat = Stat::read(process_id)?;
+    if !stat.is_running() {
+        return None;
+    }
+    stat.field(22)?.parse().ok()
+}
+
+/// Checks whether a specific process is still running.
+pub(crate) fn is_running(process_id: u32, start_time: u64) -> bool {
+    self::start_time(process_id) == Some(start_time)
+}
+
+/// Checks whether any process in the process group is still running.
+///
+/// Processes that have exited but not yet been reaped don't count.
+pub(crate) fn is_group_running(process_group_id: u32) -> bool {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return false;
+    };
+    let process_group_id = process_group_id.to_string();
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+        .filter_map(Stat::read)
+        .any(|stat| stat.is_running() && stat.field(5) == Some(process_group_id.as_str()))
+}
+
+// The contents of `/proc/<pid>/stat`, after the command name.
+struct Stat(String);
+
+impl Stat {
+    fn read(process_id: u32) -> Option<Self> {
+        let stat = fs::read_to_string(format!("/proc/{}/stat", process_id)).ok()?;
+        // the command name is in parentheses and can contain anything, so we
+        // skip past it before splitting the rest into fields
+        let (_, fields) = stat.rsplit_once(')')?;
+        Some(Self(fields.to_owned()))
+    }
+
+    // The fields are numbered from 1, as in `proc(5)`, and we've skipped the
+    // first two.
+    fn field(&self, number: usize) -> Option<&str> {
+        self.0.split_whitespace().nth(number - 3)
+    }
+
+    fn is_running(&self) -> bool {
+        !matches!(self.field(3), Some("Z") | Some("X") | None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+
+    use crate::test_helpers::*;
+
+    use super::*;
+
+    #[test]
+    fn test_finds_the_start_time_of_a_running_process() {
+        let actual = start_time(std::process::id());
+
+        assert!(actual.is_some(), "Expected a start time.");
+        assert!(is_running(std::process::id(), actual.unwrap()));
+    }
+
+    #[test]
+    fn test_does_not_consider_a_zombie_process_to_be_running() -> anyhow::Result<()> {
+        let child = Command::new("true").spawn()?;
+        let process_id = child.id();
+
+        // we don't wait for the child, so it stays around as a zombie
+        eventually(|| test_eq(start_time(process_id), None))
+    }
+
+    #[test]
+    fn test_checks_whether_a_process_group_is_running() -> anyhow::Result<()> {
+        let mut child = Command::new("sleep").arg("60").process_group(0).spawn()?;
+        let process_group_id = child.id();
+
+        assert!(is_group_running(process_group_id));
+
+        child.kill()?;
+        child.wait()?;
+
+        assert!(!is_group_running(process_group_id));
+        Ok(())
+    }
+
+    #[test]
+    fn test_does_not_consider_a_different_process_to_be_running() {
+        let actual = start_time(std::process::id()).unwrap();
+
+        assert!(!is_running(std::process::id(), actual + 1));
+    }
+}
@@ -0,0 +1,183 @@
+use crate::communication::ExitStatus;
+use crate::timing::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RestartPolicy {
+    #[default]
+    Never,
+    OnFailure {
+        max_retries: Option<u32>,
+        backoff: Duration,
+    },
+    Always {
+        max_retries: Option<u32>,
+        backoff: Duration,
+    },
+}
+
+impl RestartPolicy {
+    /// Decides whether a service should be restarted after exiting, given the
+    /// number of times it has already been restarted.
+    ///
+    /// If so, returns the time to wait before restarting it. This doubles with
+    /// each restart, up to a maximum.
+    pub(crate) fn delay_before_restart(
+        &self,
+        exit_status: &ExitStatus,
+        restarts: u32,
+    ) -> Option<Duration> {
+        let (max_retries, backoff) = match self {
+            Self::Never => {
+                return None;
+            }
+            Self::OnFailure {
+                max_retries,
+                backoff,
+            } => {
+                if *exit_status == ExitStatus::ExitedWithCode(0) {
+                    return None;
+                }
+                (max_retries, backoff)
+            }
+            Self::Always {
+                max_retries,
+                backoff,
+            } => (max_retries, backoff),
+        };
+        if max_retries.map_or(false, |max_retries| restarts >= max_retries) {
+            return None;
+        }
+        let factor = 2u32.saturating_pow(restarts);
+        Some(
+            backoff
+                .saturating_mul(factor)
+                .min(Duration::MAX_RESTART_BACKOFF),
+        )
+    }
+}
+
+impl std::fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (description, max_retries, backoff) = match self {
+            RestartPolicy::Never => {
+                return write!(f, "never");
+            }
+            RestartPolicy::OnFailure {
+                max_retries,
+                backoff,
+            } => ("on failure", max_retries, backoff),
+            RestartPolicy::Always {
+                max_retries,
+                backoff,
+            } => ("always", max_retries, backoff),
+        };
+        write!(f, "{} (backoff: {}", description, backoff)?;
+        if let Some(max_retries) = max_retries {
+            write!(f, ", max retries: {}", max_retries)?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timing::DurationUnit;
+
+    use super::*;
+
+    #[test]
+    fn test_never_restarts() {
+        let policy = RestartPolicy::Never;
+
+        assert_eq!(
+            policy.delay_before_restart(&ExitStatus::ExitedWithCode(1), 0),
+            None
+        );
+    }
+
+    #[test]
+    fn test_restarts_on_failure() {
+        let policy = RestartPolicy::OnFailure {
+            max_retries: None,
+            backoff: Duration::of(1, DurationUnit::Seconds),
+        };
+
+        assert_eq!(
+            policy.delay_before_restart(&ExitStatus::ExitedWithCode(1), 0),
+            Some(Duration::of(1, DurationUnit::Seconds))
+        );
+        assert_eq!(
+            policy.delay_before_restart(&ExitStatus::ExitedWithSignal(9), 0),
+            Some(Duration::of(1, DurationUnit::Seconds))
+        );
+        assert_eq!(
+            policy.delay_before_restart(&ExitStatus::ExitedWithCode(0), 0),
+            None
+        );
+    }
+
+    #[test]
+    fn test_always_restarts() {
+        let policy = RestartPolicy::Always {
+            max_retries: None,
+            backoff: Duration::of(1, DurationUnit::Seconds),
+        };
+
+        assert_eq!(
+            policy.delay_before_restart(&ExitStatus::ExitedWithCode(0), 0),
+            Some(Duration::of(1, DurationUnit::Seconds))
+        );
+    }
+
+    #[test]
+    fn test_backs_off_exponentially() {
+        let policy = RestartPolicy::Always {
+            max_retries: None,
+            backoff: Duration::of(100, DurationUnit::Milliseconds),
+        };
+
+        let delays = (0..4)
+            .map(|restarts| policy.delay_before_restart(&ExitStatus::ExitedWithCode(0), restarts))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            delays,
+            vec![
+                Some(Duration::of(100, DurationUnit::Milliseconds)),
+                Some(Duration::of(200, DurationUnit::Milliseconds)),
+                Some(Duration::of(400, DurationUnit::Milliseconds)),
+                Some(Duration::of(800, DurationUnit::Milliseconds)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_limits_the_backoff() {
+        let policy = RestartPolicy::Always {
+            max_retries: None,
+            backoff: Duration::of(1, DurationUnit::Seconds),
+        };
+
+        assert_eq!(
+            policy.delay_before_restart(&ExitStatus::ExitedWithCode(0), 40),
+            Some(Duration::MAX_RESTART_BACKOFF)
+        );
+    }
+
+    #[test]
+    fn test_stops_restarting_after_the_maximum_number_of_retries() {
+        let policy = RestartPolicy::OnFailure {
+            max_retries: Some(2),
+            backoff: Duration::of(1, DurationUnit::Seconds),
+        };
+
+        assert!(policy
+            .delay_before_restart(&ExitStatus::ExitedWithCode(1), 1)
+            .is_some());
+        assert_eq!(
+            policy.delay_before_restart(&ExitStatus::ExitedWithCode(1), 2),
+            None
+        );
+    }
+}
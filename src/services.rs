@@ -1,20 +1,42 @@
+pub mod nix_programs;
 pub mod programs;
 
+pub use nix_programs::*;
 pub use programs::*;
 
 use crate::communication::ExitStatus;
 use crate::error::DaemonResult;
+use crate::output::Output;
 use crate::timing::Duration;
 
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum Service {
     Program(Program),
+    Nix(NixProgram),
+}
+
+// An empty program, which is only useful as a placeholder, so that requests can
+// be built with `..Default::default()`.
+impl Default for Service {
+    fn default() -> Self {
+        Self::Program(Program::default())
+    }
 }
 
 impl Service {
-    pub(crate) fn start(&self) -> DaemonResult<RunningService> {
+    /// Starts the service, giving up if preparing it takes longer than the
+    /// timeout. This doesn't include waiting for it to be ready.
+    pub(crate) fn start(&self, output: &Output, timeout: Duration) -> DaemonResult<RunningService> {
+        match self {
+            Self::Program(p) => p.start(output).map(RunningService::Program),
+            Self::Nix(p) => p.start(output, timeout).map(RunningService::Program),
+        }
+    }
+
+    pub(crate) fn adopt(&self, process_id: u32, start_time: Option<u64>) -> RunningService {
         match self {
-            Self::Program(p) => p.start().map(RunningService::Program),
+            Self::Program(p) => RunningService::Program(p.adopt(process_id, start_time)),
+            Self::Nix(p) => RunningService::Program(p.adopt(process_id, start_time)),
         }
     }
 }
@@ -24,9 +46,21 @@ pub(crate) enum RunningService {
 }
 
 impl RunningService {
-    pub(crate) fn is_running(&mut self) -> DaemonResult<bool> {
+    pub(crate) fn process_id(&self) -> u32 {
+        match self {
+            Self::Program(p) => p.process_id(),
+        }
+    }
+
+    pub(crate) fn start_time(&self) -> Option<u64> {
+        match self {
+            Self::Program(p) => p.start_time(),
+        }
+    }
+
+    pub(crate) fn exit_status(&mut self) -> DaemonResult<Option<ExitStatus>> {
         match self {
-            Self::Program(p) => p.is_running(),
+            Self::Program(p) => p.exit_status(),
         }
     }
 
@@ -0,0 +1,527 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+use lazy_static::lazy_static;
+
+use crate::error::{DaemonError, DaemonResult, InvalidNixEnvironment};
+use crate::log;
+use crate::output::Output;
+use crate::services::programs::{check_directory, Program, RunningProgram};
+use crate::timing::Duration;
+
+/// Where to find a Nix development environment.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum NixEnvironment {
+    /// A flake reference, such as `.#dev` or `github:owner/repository`.
+    Flake(String),
+    /// A Nix file which evaluates to a derivation, such as `shell.nix`.
+    File(PathBuf),
+}
+
+/// A program run inside a Nix development environment, as if by
+/// `nix develop`.
+///
+/// Relative paths are resolved against the program's working directory. The
+/// environment is realised with `nix print-dev-env`, using the program's
+/// `PATH` to find `nix`, and cached until its inputs change. Shell hooks are
+/// not run.
+///
+/// The Nix environment sits underneath the program's `.env` files and
+/// explicit environment, with its `PATH` in front of the one the program
+/// would otherwise see.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct NixProgram {
+    pub environment: NixEnvironment,
+    pub program: Program,
+}
+
+type Variables = BTreeMap<String, String>;
+
+// Where an environment comes from: the reference, where it's resolved from,
+// and the `PATH` used to find `nix`.
+type Source = (NixEnvironment, Option<PathBuf>, Option<OsString>);
+
+// The contents of the local files an environment is defined by.
+type Contents = Vec<Option<Vec<u8>>>;
+
+lazy_static! {
+    // Only the latest realisation of each source is kept, so that changing
+    // the files doesn't leave stale environments behind.
+    static ref REALISED: Mutex<HashMap<Source, (Contents, Variables)>> =
+        Mutex::new(HashMap::new());
+}
+
+// These are specific to the build sandbox, so `nix develop` ignores them too.
+const IGNORED_VARIABLES: &[&str] = &[
+    "BASHOPTS",
+    "HOME",
+    "NIX_BUILD_TOP",
+    "NIX_ENFORCE_PURITY",
+    "NIX_LOG_FD",
+    "NIX_REMOTE",
+    "PPID",
+    "SHELL",
+    "SHELLOPTS",
+    "SSL_CERT_FILE",
+    "TEMP",
+    "TEMPDIR",
+    "TERM",
+    "TMP",
+    "TMPDIR",
+    "TZ",
+    "UID",
+];
+
+impl NixProgram {
+    /// Starts the program, giving up on realising the environment if it takes
+    /// longer than the timeout.
+    pub(crate) fn start(&self, output: &Output, timeout: Duration) -> DaemonResult<RunningProgram> {
+        let variables = self.realise(timeout)?;
+        let mut program = self.program.clone();
+        // the Nix environment's `PATH` comes first, but we keep the rest
+        let path = match (variables.get("PATH"), self.program.underlying("PATH")) {
+            (Some(nix_path), Some(path)) => {
+                let mut joined = std::ffi::OsString::from(nix_path);
+                joined.push(":");
+                joined.push(path);
+                Some(joined)
+            }
+            (Some(nix_path), None) => Some(nix_path.into()),
+            (None, path) => path,
+        };
+        program.base_environment = variables
+            .into_iter()
+            .map(|(name, value)| (name.into(), value.into()))
+            .chain(self.program.base_environment.clone())
+            .collect();
+        if let Some(path) = path {
+            program.base_environment.insert("PATH".into(), path.into());
+        }
+        program.start(output)
+    }
+
+    pub(crate) fn adopt(&self, process_id: u32, start_time: Option<u64>) -> RunningProgram {
+        self.program.adopt(process_id, start_time)
+    }
+
+    // Realises the environment, unless we have already done so with the same
+    // inputs.
+    fn realise(&self, timeout: Duration) -> DaemonResult<Variables> {
+        if let Some(working_directory) = &self.program.working_directory {
+            check_directory(working_directory)?;
+        }
+        let source = (
+            self.environment.clone(),
+            self.program.working_directory.clone(),
+            self.program.lookup("PATH"),
+        );
+        let contents = self.read_inputs();
+        if let Some((realised_contents, variables)) = REALISED.lock().unwrap().get(&source) {
+            if *realised_contents == contents {
+                return Ok(variables.clone());
+            }
+        }
+
+        let invalid = |message: String| {
+            DaemonError::NixEnvironmentError(InvalidNixEnvironment {
+                environment: self.environment.to_string(),
+                message,
+            })
+        };
+        let mut command = Command::new("nix");
+        command.args([
+            "--extra-experimental-features",
+            "nix-command flakes",
+            "print-dev-env",
+            "--json",
+        ]);
+        match &self.environment {
+            NixEnvironment::Flake(reference) => command.arg(reference),
+            NixEnvironment::File(path) => command.arg("--file").arg(path),
+        };
+        if let Some(path) = self.program.lookup("PATH") {
+            command.env("PATH", path);
+        }
+        if let Some(working_directory) = &self.program.working_directory {
+            command.current_dir(working_directory);
+        }
+        // we start a new process group, so that we can stop anything `nix`
+        // starts if it takes too long
+        command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0);
+        let mut child = command
+            .spawn()
+            .map_err(|error| invalid(error.to_string()))?;
+        // we read in the background, so that `nix` doesn't block on a full pipe
+        let stdout = read_in_background(child.stdout.take());
+        let stderr = read_in_background(child.stderr.take());
+        // if the deadline is too far away to represent, we wait forever
+        let deadline = Instant::now().checked_add(timeout.into());
+        let status = loop {
+            if let Some(status) = child
+                .try_wait()
+                .map_err(|error| invalid(error.to_string()))?
+            {
+                break status;
+            }
+            if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+                match child.id().try_into() {
+                    Ok(process_id) => {
+                        let process_group = nix::unistd::Pid::from_raw(process_id);
+                        let _ = nix::sys::signal::killpg(process_group, nix::sys::signal::SIGKILL);
+                    }
+                    // we can't name the process group, so we settle for `nix`
+                    Err(_) => {
+                        let _ = child.kill();
+                    }
+                }
+                let _ = child.wait();
+                return Err(invalid(format!("timed out after {}", timeout)));
+            }
+            Duration::QUANTUM.sleep();
+        };
+        let stdout = stdout.join().unwrap_or_default();
+        let stderr = stderr.join().unwrap_or_default();
+        if !status.success() {
+            return Err(invalid(String::from_utf8_lossy(&stderr).trim().to_owned()));
+        }
+        let variables = parse(&stdout).map_err(|error| invalid(error.to_string()))?;
+        log::info!(
+            event = "NIX_ENVIRONMENT_REALISED",
+            environment = self.environment
+        );
+
+        REALISED
+            .lock()
+            .unwrap()
+            .insert(source, (contents, variables.clone()));
+        Ok(variables)
+    }
+
+    // Reads the local files the environment is defined by, if any, including
+    // the flake lock. Other inputs, such as imported files, are not
+    // considered.
+    fn read_inputs(&self) -> Contents {
+        let files = match &self.environment {
+            NixEnvironment::Flake(reference) => {
+                let location = reference.split('#').next().unwrap_or_default();
+                let directory = self.resolve(location.strip_prefix("path:").unwrap_or(location));
+                vec![directory.join("flake.nix"), directory.join("flake.lock")]
+            }
+            NixEnvironment::File(path) => vec![self.resolve(path)],
+        };
+        // a missing file is fine; `nix` will tell us if it matters
+        files
+            .into_iter()
+            .map(|file| std::fs::read(file).ok())
+            .collect()
+    }
+
+    fn resolve(&self, path: impl AsRef<Path>) -> PathBuf {
+        match &self.program.working_directory {
+            Some(working_directory) => working_directory.join(path),
+            None => path.as_ref().to_owned(),
+        }
+    }
+}
+
+impl std::fmt::Display for NixEnvironment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Flake(reference) => write!(f, "{}", reference),
+            Self::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+fn read_in_background(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut contents = Vec::new();
+        if let Some(mut pipe) = pipe {
+            // if reading fails, we'll find out when we try to parse it
+            let _ = pipe.read_to_end(&mut contents);
+        }
+        contents
+    })
+}
+
+// Reads the output of `nix print-dev-env --json`, keeping only the exported
+// variables.
+fn parse(output: &[u8]) -> serde_json::Result<Variables> {
+    #[derive(serde::Deserialize)]
+    struct DevEnvironment {
+        variables: BTreeMap<String, DevVariable>,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct DevVariable {
+        #[serde(rename = "type")]
+        kind: String,
+        value: serde_json::Value,
+    }
+
+    let environment: DevEnvironment = serde_json::from_slice(output)?;
+    Ok(environment
+        .variables
+        .into_iter()
+        .filter(|(name, _)| !IGNORED_VARIABLES.contains(&name.as_str()))
+        .filter_map(
+            |(name, variable)| match (variable.kind.as_str(), variable.value) {
+                ("exported", serde_json::Value::String(value)) => Some((name, value)),
+                _ => None,
+            },
+        )
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::InvalidDirectory;
+    use crate::programs::Environment;
+    use crate::test_helpers::*;
+    use crate::test_programs;
+
+    use super::*;
+
+    const DEV_ENVIRONMENT: &str = r#"{
+        "bashFunctions": {"greet": "echo hello"},
+        "variables": {
+            "GREETING": {"type": "exported", "value": "hello from nix"},
+            "HOME": {"type": "exported", "value": "/homeless-shelter"},
+            "PATH": {"type": "exported", "value": "/nix/store/fake/bin"},
+            "shellHook": {"type": "var", "value": "echo hook"},
+            "outputs": {"type": "array", "value": ["out"]}
+        }
+    }"#;
+
+    // Writes a fake `nix` which records each call and prints the environment.
+    fn stub_nix(directory: &Path) -> anyhow::Result<(PathBuf, OsString)> {
+        let (bin, path) = test_programs::stub_nix(
+            directory,
+            "set -e\ndir=\"$(dirname \"$0\")\"\necho \"$@\" >> \"$dir/calls\"\ncat \"$dir/dev-environment.json\"\n",
+        )?;
+        std::fs::write(bin.join("dev-environment.json"), DEV_ENVIRONMENT)?;
+        Ok((bin, path))
+    }
+
+    fn calls(bin: &Path) -> anyhow::Result<Vec<String>> {
+        Ok(std::fs::read_to_string(bin.join("calls"))?
+            .lines()
+            .map(|line| line.to_owned())
+            .collect())
+    }
+
+    #[test]
+    #[ntest::timeout(5000)]
+    fn test_runs_a_program_within_a_flake_environment() -> anyhow::Result<()> {
+        let temporary_directory = tempfile::tempdir()?;
+        let working_directory = temporary_directory.path().to_owned();
+        let (bin, path) = stub_nix(&working_directory)?;
+        std::fs::write(working_directory.join("flake.nix"), "{ outputs = _: {}; }")?;
+
+        let program = NixProgram {
+            environment: NixEnvironment::Flake(".#dev".to_owned()),
+            program: Program {
+                command: "bash".into(),
+                arguments: vec![
+                    "-c".into(),
+                    "echo \"$GREETING:$HOME:$PATH\" > test.file".into(),
+                ],
+                environment: Environment::from([("HOME".into(), "/home/me".into())]),
+                base_environment: Environment::from([("PATH".into(), path.clone().into())]),
+                working_directory: Some(working_directory.clone()),
+                ..Default::default()
+            },
+        };
+        program.start(&Output::Inherit, Duration::START_TIMEOUT)?;
+
+        eventually(|| {
+            let output = std::fs::read_to_string(working_directory.join("test.file"))?;
+            test_eq(
+                output,
+                format!(
+                    "hello from nix:/home/me:/nix/store/fake/bin:{}\n",
+                    path.to_string_lossy()
+                ),
+            )
+        })?;
+        assert_eq!(
+            calls(&bin)?,
+            vec!["--extra-experimental-features nix-command flakes print-dev-env --json .#dev"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[ntest::timeout(5000)]
+    fn test_realises_an_environment_once_until_it_changes() -> anyhow::Result<()> {
+        let temporary_directory = tempfile::tempdir()?;
+        let working_directory = temporary_directory.path().to_owned();
+        let (bin, path) = stub_nix(&working_directory)?;
+        std::fs::write(working_directory.join("shell.nix"), "{ }")?;
+
+        let program = NixProgram {
+            environment: NixEnvironment::File("shell.nix".into()),
+            program: Program {
+                command: "true".into(),
+                base_environment: Environment::from([("PATH".into(), path.into())]),
+                working_directory: Some(working_directory.clone()),
+                ..Default::default()
+            },
+        };
+        program.start(&Output::Inherit, Duration::START_TIMEOUT)?;
+        program.start(&Output::Inherit, Duration::START_TIMEOUT)?;
+
+        assert_eq!(calls(&bin)?.len(), 1);
+
+        std::fs::write(
+            working_directory.join("shell.nix"),
+            "{ pkgs ? import <nixpkgs> {} }",
+        )?;
+        program.start(&Output::Inherit, Duration::START_TIMEOUT)?;
+
+        assert_eq!(
+            calls(&bin)?,
+            vec![
+                "--extra-experimental-features nix-command flakes print-dev-env --json --file shell.nix";
+                2
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[ntest::timeout(5000)]
+    fn test_environment_files_override_the_nix_environment() -> anyhow::Result<()> {
+        let temporary_directory = tempfile::tempdir()?;
+        let working_directory = temporary_directory.path().to_owned();
+        let (_, path) = stub_nix(&working_directory)?;
+        std::fs::write(working_directory.join("shell.nix"), "{ }")?;
+        std::fs::write(
+            working_directory.join(".env"),
+            "GREETING=\"${GREETING}, and from .env\"\n",
+        )?;
+
+        let program = NixProgram {
+            environment: NixEnvironment::File("shell.nix".into()),
+            program: Program {
+                command: "bash".into(),
+                arguments: vec!["-c".into(), "echo \"$GREETING\" > test.file".into()],
+                base_environment: Environment::from([("PATH".into(), path.into())]),
+                environment_files: vec![".env".into()],
+                working_directory: Some(working_directory.clone()),
+                ..Default::default()
+            },
+        };
+        program.start(&Output::Inherit, Duration::START_TIMEOUT)?;
+
+        eventually(|| {
+            let output = std::fs::read_to_string(working_directory.join("test.file"))?;
+            test_eq(output.as_str(), "hello from nix, and from .env\n")
+        })
+    }
+
+    #[test]
+    #[ntest::timeout(5000)]
+    fn test_gives_up_on_realising_an_environment_after_the_timeout() -> anyhow::Result<()> {
+        let temporary_directory = tempfile::tempdir()?;
+        let (_, path) = test_programs::stub_nix(temporary_directory.path(), "sleep 60\n")?;
+
+        let program = NixProgram {
+            environment: NixEnvironment::Flake(".#slow".to_owned()),
+            program: Program {
+                command: "true".into(),
+                base_environment: Environment::from([("PATH".into(), path.into())]),
+                working_directory: Some(temporary_directory.path().to_owned()),
+                ..Default::default()
+            },
+        };
+        let start_time = Instant::now();
+        let result = program.start(
+            &Output::Inherit,
+            Duration::of(500, crate::timing::DurationUnit::Milliseconds),
+        );
+        let elapsed = Instant::now() - start_time;
+
+        assert_eq!(
+            result.err(),
+            Some(DaemonError::NixEnvironmentError(InvalidNixEnvironment {
+                environment: ".#slow".to_owned(),
+                message: "timed out after 500ms".to_owned(),
+            }))
+        );
+        assert!(
+            elapsed < std::time::Duration::from_secs(2),
+            "Expected the elapsed time of {:?} to be approximately 500ms.",
+            elapsed
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_reports_a_failure_to_realise_an_environment() -> anyhow::Result<()> {
+        let temporary_directory = tempfile::tempdir()?;
+        let (_, path) = test_programs::stub_nix(
+            temporary_directory.path(),
+            "echo 'no such flake' >&2\nexit 1\n",
+        )?;
+
+        let program = NixProgram {
+            environment: NixEnvironment::Flake("./missing".to_owned()),
+            program: Program {
+                command: "true".into(),
+                base_environment: Environment::from([("PATH".into(), path.into())]),
+                working_directory: Some(temporary_directory.path().to_owned()),
+                ..Default::default()
+            },
+        };
+        let result = program.start(&Output::Inherit, Duration::START_TIMEOUT);
+
+        assert_eq!(
+            result.err(),
+            Some(DaemonError::NixEnvironmentError(InvalidNixEnvironment {
+                environment: "./missing".to_owned(),
+                message: "no such flake".to_owned(),
+            }))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_reports_a_missing_working_directory() -> anyhow::Result<()> {
+        let temporary_directory = tempfile::tempdir()?;
+        let (bin, path) = stub_nix(temporary_directory.path())?;
+        let working_directory = temporary_directory.path().join("missing");
+
+        let program = NixProgram {
+            environment: NixEnvironment::File("shell.nix".into()),
+            program: Program {
+                command: "true".into(),
+                base_environment: Environment::from([("PATH".into(), path.into())]),
+                working_directory: Some(working_directory.clone()),
+                ..Default::default()
+            },
+        };
+        let result = program.start(&Output::Inherit, Duration::START_TIMEOUT);
+
+        assert!(
+            matches!(
+                result,
+                Err(DaemonError::WorkingDirectoryError(InvalidDirectory { ref path, .. }))
+                    if *path == working_directory
+            ),
+            "Expected a working directory error."
+        );
+        assert!(!bin.join("calls").exists(), "Expected not to call `nix`.");
+        Ok(())
+    }
+}
@@ -1,16 +1,21 @@
 use std::collections::BTreeMap;
 use std::ffi::{OsStr, OsString};
-use std::os::unix::process::ExitStatusExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
 use std::process::{Child, Command};
 use std::time::Instant;
 
 use bstr::{ByteSlice, ByteVec};
 
-use crate::error::{DaemonError, DaemonResult};
+use crate::error::{DaemonError, DaemonResult, InvalidDirectory, InvalidEnvironmentFile};
+use crate::output::{Output, Stream};
+use crate::processes;
 use crate::timing::Duration;
 use crate::ExitStatus;
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+mod dotenv;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Argument(OsString);
 
 impl serde::Serialize for Argument {
@@ -82,56 +87,229 @@ impl From<std::path::PathBuf> for Argument {
 
 pub type Environment = BTreeMap<Argument, Argument>;
 
-#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+/// Which of the daemon's own environment variables a program can see, in
+/// addition to those provided explicitly.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum EnvironmentPolicy {
+    /// The program inherits the daemon's whole environment.
+    #[default]
+    Inherit,
+    /// The program sees only the variables provided.
+    Clean,
+    /// The program sees only the variables provided, plus these variables
+    /// from the daemon's environment.
+    Allowlist(Vec<Argument>),
+}
+
+impl EnvironmentPolicy {
+    /// Checks whether the program can see this variable from the daemon's
+    /// environment.
+    pub fn inherits(&self, name: &OsStr) -> bool {
+        match self {
+            Self::Inherit => true,
+            Self::Clean => false,
+            Self::Allowlist(names) => names.iter().any(|allowed| allowed.0 == name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct Program {
     pub command: Argument,
     pub arguments: Vec<Argument>,
+    /// Extra environment variables for the program, which take precedence
+    /// over everything else.
+    ///
+    /// If `PATH` is set, here or in any other layer, it is also used to find
+    /// the command.
     pub environment: Environment,
+    /// Environment variables applied underneath `.env` files and
+    /// `environment`, such as those passed on from the client.
+    #[serde(default)]
+    pub base_environment: Environment,
+    #[serde(default)]
+    pub environment_policy: EnvironmentPolicy,
+    /// `.env` files to load variables from, relative to the working
+    /// directory. They override `base_environment`, later files override
+    /// earlier ones, and `environment` overrides them all.
+    #[serde(default)]
+    pub environment_files: Vec<PathBuf>,
+    /// Where to run the program.
+    ///
+    /// If this is not set, the program runs in the daemon's working directory.
+    #[serde(default)]
+    pub working_directory: Option<PathBuf>,
 }
 
 pub struct RunningProgram {
-    process: Child,
+    process_id: u32,
+    start_time: Option<u64>,
+    // we only have a handle on the process if we started it ourselves
+    child: Option<Child>,
 }
 
 impl Program {
-    pub(crate) fn start(&self) -> DaemonResult<RunningProgram> {
-        let process = Command::new(&self.command)
-            .args(&self.arguments)
-            .envs(&self.environment)
+    pub(crate) fn start(&self, output: &Output) -> DaemonResult<RunningProgram> {
+        let mut command = Command::new(&self.command);
+        command.args(&self.arguments);
+        if self.environment_policy != EnvironmentPolicy::Inherit {
+            command.env_clear().envs(
+                std::env::vars_os().filter(|(name, _)| self.environment_policy.inherits(name)),
+            );
+        }
+        if let Some(working_directory) = &self.working_directory {
+            // we check up front, as otherwise it looks like the command is missing
+            check_directory(working_directory)?;
+            command.current_dir(working_directory);
+        }
+        command.envs(&self.base_environment);
+        command.envs(self.load_environment_files()?);
+        command.envs(&self.environment);
+        // we start a new process group, so that we can stop the program along
+        // with anything it starts
+        command.process_group(0);
+        if let Some(stdout) = output
+            .open(Stream::Stdout)
+            .map_err(|error| DaemonError::CaptureOutputError(error.into()))?
+        {
+            command.stdout(stdout);
+        }
+        if let Some(stderr) = output
+            .open(Stream::Stderr)
+            .map_err(|error| DaemonError::CaptureOutputError(error.into()))?
+        {
+            command.stderr(stderr);
+        }
+        let child = command
             .spawn()
             .map_err(|error| DaemonError::StartProcessError(error.into()))?;
-        Ok(RunningProgram { process })
+        Ok(RunningProgram {
+            process_id: child.id(),
+            start_time: processes::start_time(child.id()),
+            child: Some(child),
+        })
+    }
+
+    /// Takes responsibility for a process started by someone else, such as a
+    /// previous instance of the daemon.
+    ///
+    /// The start time is used to check that the process is the same one, as
+    /// process IDs can be reused.
+    pub(crate) fn adopt(&self, process_id: u32, start_time: Option<u64>) -> RunningProgram {
+        RunningProgram {
+            process_id,
+            start_time,
+            child: None,
+        }
+    }
+
+    fn load_environment_files(&self) -> DaemonResult<dotenv::Variables> {
+        let mut variables = dotenv::Variables::new();
+        for file in &self.environment_files {
+            let path = match &self.working_directory {
+                Some(working_directory) => working_directory.join(file),
+                None => file.clone(),
+            };
+            let invalid = |message: String| {
+                DaemonError::EnvironmentFileError(InvalidEnvironmentFile {
+                    path: path.clone(),
+                    message,
+                })
+            };
+            let contents =
+                std::fs::read_to_string(&path).map_err(|error| invalid(error.to_string()))?;
+            // interpolation sees the same values as the program will
+            let to_string = |value: OsString| value.to_string_lossy().into_owned();
+            dotenv::load(
+                &contents,
+                &mut variables,
+                |name| self.explicit(name).map(to_string),
+                |name| self.underlying(name).map(to_string),
+            )
+            .map_err(invalid)?;
+        }
+        Ok(variables)
+    }
+
+    /// Finds a variable the program would see if it weren't for its `.env`
+    /// files.
+    pub(crate) fn lookup(&self, name: &str) -> Option<OsString> {
+        self.explicit(name).or_else(|| self.underlying(name))
+    }
+
+    fn explicit(&self, name: &str) -> Option<OsString> {
+        self.environment
+            .get(&Argument::from(name))
+            .map(|value| value.0.clone())
+    }
+
+    /// Finds a variable from the layers underneath the `.env` files.
+    pub(crate) fn underlying(&self, name: &str) -> Option<OsString> {
+        match self.base_environment.get(&Argument::from(name)) {
+            Some(value) => Some(value.0.clone()),
+            None if self.environment_policy.inherits(name.as_ref()) => std::env::var_os(name),
+            None => None,
+        }
+    }
+}
+
+pub(crate) fn check_directory(path: &Path) -> DaemonResult<()> {
+    let invalid = |message: String| {
+        DaemonError::WorkingDirectoryError(InvalidDirectory {
+            path: path.to_owned(),
+            message,
+        })
+    };
+    match std::fs::metadata(path) {
+        Ok(metadata) if metadata.is_dir() => Ok(()),
+        Ok(_) => Err(invalid("not a directory".to_owned())),
+        Err(error) => Err(invalid(error.to_string())),
     }
 }
 
 impl RunningProgram {
+    pub(crate) fn process_id(&self) -> u32 {
+        self.process_id
+    }
+
+    pub(crate) fn start_time(&self) -> Option<u64> {
+        self.start_time
+    }
+
+    #[cfg(test)]
     pub(crate) fn is_running(&mut self) -> DaemonResult<bool> {
-        let exit_code = self
-            .process
-            .try_wait()
-            .map_err(|error| DaemonError::CheckProcessError(error.into()))?;
-        Ok(exit_code.is_none())
+        Ok(self.exit_status()?.is_none())
+    }
+
+    pub(crate) fn exit_status(&mut self) -> DaemonResult<Option<ExitStatus>> {
+        match &mut self.child {
+            Some(child) => {
+                let exit_status = child
+                    .try_wait()
+                    .map_err(|error| DaemonError::CheckProcessError(error.into()))?;
+                Ok(exit_status.map(ExitStatus::from))
+            }
+            // we can't find out how an adopted process exited, only that it did
+            None => match self.start_time {
+                Some(start_time) if processes::is_running(self.process_id, start_time) => Ok(None),
+                _ => Ok(Some(ExitStatus::None)),
+            },
+        }
     }
 
+    /// Stops the program, and anything else in its process group.
+    ///
+    /// The exit status is that of the program itself, but we wait for the
+    /// rest of the group to stop too.
     pub(crate) fn stop(&mut self, timeout: Duration) -> DaemonResult<ExitStatus> {
         let timeout_sys = std::time::Duration::from(timeout);
         self.kill(nix::sys::signal::Signal::SIGTERM)?;
         let sigterm_time = Instant::now();
         loop {
-            if let Ok(Some(exit_status)) = self.process.try_wait() {
-                return Ok(match exit_status.code() {
-                    None => match exit_status.signal() {
-                        None => ExitStatus::None,
-                        Some(signal) => match u8::try_from(signal).ok() {
-                            None => ExitStatus::None,
-                            Some(signal) => ExitStatus::ExitedWithSignal(signal),
-                        },
-                    },
-                    Some(code) => match u8::try_from(code).ok() {
-                        None => ExitStatus::None,
-                        Some(code) => ExitStatus::ExitedWithCode(code),
-                    },
-                });
+            if let Ok(Some(exit_status)) = self.exit_status() {
+                if !processes::is_group_running(self.process_id) {
+                    return Ok(exit_status);
+                }
             }
             if Instant::now() - sigterm_time > timeout_sys {
                 self.kill(nix::sys::signal::Signal::SIGKILL)?;
@@ -140,14 +318,30 @@ impl RunningProgram {
         }
     }
 
-    fn kill(&self, signal: nix::sys::signal::Signal) -> DaemonResult<()> {
-        let unwrapped_process_id = self.process.id();
+    // Signals the whole process group, or just the process if it doesn't lead
+    // a group, which is the case for processes started by older daemons.
+    //
+    // The process group ID can't be reused while anything is still in the
+    // group, so it's safe to signal even if the program itself has exited.
+    fn kill(&mut self, signal: nix::sys::signal::Signal) -> DaemonResult<()> {
+        if self.child.is_none()
+            && self.exit_status()?.is_some()
+            && !processes::is_group_running(self.process_id)
+        {
+            // the process ID might belong to someone else now
+            return Ok(());
+        }
+        let unwrapped_process_id = self.process_id;
         let process_id = nix::unistd::Pid::from_raw(
             unwrapped_process_id
                 .try_into()
                 .expect("Could not convert a process ID."),
         );
-        match nix::sys::signal::kill(process_id, signal) {
+        let result = match nix::sys::signal::killpg(process_id, signal) {
+            Err(nix::errno::Errno::ESRCH) => nix::sys::signal::kill(process_id, signal),
+            result => result,
+        };
+        match result {
             Ok(()) => Ok(()),
             Err(nix::errno::Errno::ESRCH) => Ok(()), // the process was already stopped
             Err(error) => Err(DaemonError::StopProcessError {
@@ -172,7 +366,7 @@ mod tests {
     #[ntest::timeout(2000)]
     fn test_starting_and_stopping() -> DaemonResult<()> {
         let program = test_programs::waits_for_termination();
-        let mut running_program = program.start()?;
+        let mut running_program = program.start(&Output::Inherit)?;
 
         Duration::QUANTUM.sleep();
         assert!(
@@ -203,8 +397,9 @@ mod tests {
                 ("INPUT".into(), "hello there".into()),
                 ("TEST_FILE".into(), test_file.clone().into()),
             ]),
+            ..Default::default()
         };
-        program.start()?;
+        program.start(&Output::Inherit)?;
 
         eventually(|| {
             let output = std::fs::read_to_string(&test_file)?;
@@ -212,11 +407,231 @@ mod tests {
         })
     }
 
+    #[test]
+    #[ntest::timeout(2000)]
+    fn test_clean_environment() -> anyhow::Result<()> {
+        let temporary_directory = tempfile::tempdir()?;
+        let test_file = temporary_directory.path().join("test.file");
+
+        // `bash` reads `~/.bashrc` if it can't tell it's in a nested shell,
+        // which is slow and changes the environment, so we use `sh`
+        let program = Program {
+            command: "sh".into(),
+            arguments: vec!["-c".into(), "echo \"$INPUT:$HOME\" > $TEST_FILE".into()],
+            environment: Environment::from([
+                ("INPUT".into(), "hello there".into()),
+                ("TEST_FILE".into(), test_file.clone().into()),
+            ]),
+            environment_policy: EnvironmentPolicy::Clean,
+            ..Default::default()
+        };
+        program.start(&Output::Inherit)?;
+
+        eventually(|| {
+            let output = std::fs::read_to_string(&test_file)?;
+            test_eq(output.as_str(), "hello there:\n")
+        })
+    }
+
+    #[test]
+    #[ntest::timeout(2000)]
+    fn test_allowlisted_environment() -> anyhow::Result<()> {
+        let temporary_directory = tempfile::tempdir()?;
+        let test_file = temporary_directory.path().join("test.file");
+
+        // `cargo` sets both of these for tests, so we don't need to change
+        // our own environment, which would race with other tests
+        let allowed = std::env::var("CARGO_MANIFEST_DIR")?;
+        assert!(std::env::var_os("CARGO_PKG_NAME").is_some());
+
+        let program = Program {
+            command: "sh".into(),
+            arguments: vec![
+                "-c".into(),
+                "echo \"$CARGO_MANIFEST_DIR:$CARGO_PKG_NAME:$INPUT\" > $TEST_FILE".into(),
+            ],
+            environment: Environment::from([("TEST_FILE".into(), test_file.clone().into())]),
+            base_environment: Environment::from([("INPUT".into(), "hello there".into())]),
+            environment_policy: EnvironmentPolicy::Allowlist(vec!["CARGO_MANIFEST_DIR".into()]),
+            ..Default::default()
+        };
+        program.start(&Output::Inherit)?;
+
+        eventually(|| {
+            let output = std::fs::read_to_string(&test_file)?;
+            test_eq(output, format!("{}::hello there\n", allowed))
+        })
+    }
+
+    #[test]
+    #[ntest::timeout(2000)]
+    fn test_finds_the_command_using_the_path_provided() -> anyhow::Result<()> {
+        let temporary_directory = tempfile::tempdir()?;
+        let test_file = temporary_directory.path().join("test.file");
+        let command_directory = temporary_directory.path().join("bin");
+        std::fs::create_dir(&command_directory)?;
+        std::fs::write(
+            command_directory.join("greet"),
+            "#!/bin/sh\necho hello > \"$TEST_FILE\"\n",
+        )?;
+        std::fs::set_permissions(
+            command_directory.join("greet"),
+            std::os::unix::fs::PermissionsExt::from_mode(0o755),
+        )?;
+        let path = std::env::join_paths(std::iter::once(command_directory).chain(
+            std::env::split_paths(&std::env::var_os("PATH").unwrap_or_default()),
+        ))?;
+
+        let program = Program {
+            command: "greet".into(),
+            environment: Environment::from([
+                ("PATH".into(), path.into()),
+                ("TEST_FILE".into(), test_file.clone().into()),
+            ]),
+            ..Default::default()
+        };
+        program.start(&Output::Inherit)?;
+
+        eventually(|| {
+            let output = std::fs::read_to_string(&test_file)?;
+            test_eq(output.as_str(), "hello\n")
+        })
+    }
+
+    #[test]
+    #[ntest::timeout(2000)]
+    fn test_working_directory() -> anyhow::Result<()> {
+        let temporary_directory = tempfile::tempdir()?;
+        let working_directory = temporary_directory.path().canonicalize()?;
+
+        let program = Program {
+            command: "bash".into(),
+            arguments: vec!["-c".into(), "pwd > test.file".into()],
+            working_directory: Some(working_directory.clone()),
+            ..Default::default()
+        };
+        program.start(&Output::Inherit)?;
+
+        eventually(|| {
+            let output = std::fs::read_to_string(working_directory.join("test.file"))?;
+            test_eq(output, format!("{}\n", working_directory.display()))
+        })
+    }
+
+    #[test]
+    #[ntest::timeout(2000)]
+    fn test_environment_files() -> anyhow::Result<()> {
+        let temporary_directory = tempfile::tempdir()?;
+        let working_directory = temporary_directory.path().to_owned();
+        std::fs::write(
+            working_directory.join(".env"),
+            "# shared settings\nexport GREETING=hello\nNAME='nobody'\nMESSAGE=\"${GREETING}, $NAME\"\n",
+        )?;
+        std::fs::write(working_directory.join(".env.local"), "GREETING=hi\n")?;
+
+        let program = Program {
+            command: "bash".into(),
+            arguments: vec![
+                "-c".into(),
+                "echo \"$GREETING:$NAME:$MESSAGE\" > test.file".into(),
+            ],
+            environment: Environment::from([("NAME".into(), "world".into())]),
+            environment_files: vec![".env".into(), ".env.local".into()],
+            working_directory: Some(working_directory.clone()),
+            ..Default::default()
+        };
+        program.start(&Output::Inherit)?;
+
+        eventually(|| {
+            let output = std::fs::read_to_string(working_directory.join("test.file"))?;
+            test_eq(output.as_str(), "hi:world:hello, world\n")
+        })
+    }
+
+    #[test]
+    fn test_environment_files_extend_the_base_environment() -> anyhow::Result<()> {
+        let temporary_directory = tempfile::tempdir()?;
+        let working_directory = temporary_directory.path().to_owned();
+        std::fs::write(
+            working_directory.join(".env"),
+            "PATH=/opt/tools:${PATH}\nGREETING=hello\n",
+        )?;
+        let path = std::env::var_os("PATH").unwrap_or_default();
+
+        let program = Program {
+            command: "bash".into(),
+            arguments: vec!["-c".into(), "echo \"$GREETING:$PATH\" > test.file".into()],
+            base_environment: Environment::from([
+                ("PATH".into(), path.clone().into()),
+                ("GREETING".into(), "hi".into()),
+            ]),
+            environment_files: vec![".env".into()],
+            working_directory: Some(working_directory.clone()),
+            ..Default::default()
+        };
+        program.start(&Output::Inherit)?;
+
+        eventually(|| {
+            let output = std::fs::read_to_string(working_directory.join("test.file"))?;
+            test_eq(
+                output,
+                format!("hello:/opt/tools:{}\n", path.to_string_lossy()),
+            )
+        })
+    }
+
+    #[test]
+    fn test_refuses_to_start_with_a_missing_environment_file() -> anyhow::Result<()> {
+        let temporary_directory = tempfile::tempdir()?;
+        let working_directory = temporary_directory.path().to_owned();
+
+        let program = Program {
+            command: "true".into(),
+            environment_files: vec![".env".into()],
+            working_directory: Some(working_directory.clone()),
+            ..Default::default()
+        };
+        let result = program.start(&Output::Inherit);
+
+        assert!(
+            matches!(
+                result,
+                Err(DaemonError::EnvironmentFileError(InvalidEnvironmentFile { ref path, .. }))
+                    if *path == working_directory.join(".env")
+            ),
+            "Expected an environment file error."
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_refuses_to_start_in_a_missing_working_directory() -> anyhow::Result<()> {
+        let temporary_directory = tempfile::tempdir()?;
+        let working_directory = temporary_directory.path().join("missing");
+
+        let program = Program {
+            command: "true".into(),
+            working_directory: Some(working_directory.clone()),
+            ..Default::default()
+        };
+        let result = program.start(&Output::Inherit);
+
+        assert!(
+            matches!(
+                result,
+                Err(DaemonError::WorkingDirectoryError(InvalidDirectory { ref path, .. }))
+                    if *path == working_directory
+            ),
+            "Expected a working directory error."
+        );
+        Ok(())
+    }
+
     #[test]
     #[ntest::timeout(2000)]
     fn test_killing() -> anyhow::Result<()> {
         let program = test_programs::ignores_termination();
-        let mut running_program = program.start()?;
+        let mut running_program = program.start(&Output::Inherit)?;
 
         Duration::QUANTUM.sleep();
         assert!(
@@ -239,10 +654,9 @@ mod tests {
     fn test_stopping_a_stopped_process() -> anyhow::Result<()> {
         let program = Program {
             command: "true".into(),
-            arguments: Default::default(),
-            environment: Default::default(),
+            ..Default::default()
         };
-        let mut running_program = program.start()?;
+        let mut running_program = program.start(&Output::Inherit)?;
 
         Duration::QUANTUM.sleep();
         assert!(
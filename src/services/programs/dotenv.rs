@@ -0,0 +1,348 @@
+//! Parses `.env` files.
+//!
+//! Each line is either blank, a comment starting with `#`, or an assignment
+//! in the form `NAME=value`, optionally prefixed with `export`. Values can be:
+//!
+//! - unquoted, running to the end of the line or a ` #` comment, with
+//!   surrounding whitespace removed
+//! - in single quotes, which are taken literally
+//! - in double quotes, which understand `\n`, `\t`, `\\`, `\"` and `\$`
+//!
+//! Unquoted and double-quoted values can refer to other variables as `$NAME`
+//! or `${NAME}`. Quoted values can span several lines.
+
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+use std::str::Chars;
+
+pub(super) type Variables = BTreeMap<String, String>;
+
+/// Parses the contents of a `.env` file, adding each variable to `variables`.
+///
+/// When interpolating, `overrides` are consulted first, as they take
+/// precedence over the file when the program starts. Then come the variables
+/// that have already been loaded, and finally the `fallback`. Unknown
+/// variables are empty.
+pub(super) fn load(
+    contents: &str,
+    variables: &mut Variables,
+    overrides: impl Fn(&str) -> Option<String>,
+    fallback: impl Fn(&str) -> Option<String>,
+) -> Result<(), String> {
+    let mut parser = Parser {
+        chars: contents.chars().peekable(),
+        line: 1,
+        assignment_line: 1,
+    };
+    let lookup = |name: &str, variables: &Variables| {
+        overrides(name)
+            .or_else(|| variables.get(name).cloned())
+            .or_else(|| fallback(name))
+    };
+    while let Some((name, value)) = parser
+        .assignment(variables, &lookup)
+        .map_err(|message| format!("line {}: {}", parser.assignment_line, message))?
+    {
+        variables.insert(name, value);
+    }
+    Ok(())
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    // where the current assignment starts, as values can span several lines
+    assignment_line: usize,
+}
+
+impl<'a> Parser<'a> {
+    // Parses the next assignment, skipping blank lines and comments, or
+    // returns `None` at the end of the file.
+    fn assignment(
+        &mut self,
+        variables: &Variables,
+        lookup: &impl Fn(&str, &Variables) -> Option<String>,
+    ) -> Result<Option<(String, String)>, String> {
+        loop {
+            self.skip_spaces();
+            match self.chars.peek() {
+                None => return Ok(None),
+                Some('\n') => {
+                    self.next();
+                }
+                Some('#') => self.skip_comment(),
+                Some(_) => break,
+            }
+        }
+        self.assignment_line = self.line;
+
+        let mut name = self.name();
+        if name == "export" && self.skip_spaces() {
+            name = self.name();
+        }
+        if name.is_empty() {
+            return Err("expected a variable name".to_owned());
+        }
+        self.skip_spaces();
+        if self.chars.peek() != Some(&'=') {
+            return Err(format!("expected `=` after {:?}", name));
+        }
+        self.next();
+        self.skip_spaces();
+
+        let resolve = |name: &str| lookup(name, variables).unwrap_or_default();
+        let value = match self.chars.peek() {
+            Some('\'') => {
+                self.next();
+                self.single_quoted()?
+            }
+            Some('"') => {
+                self.next();
+                self.double_quoted(&resolve)?
+            }
+            _ => self.unquoted(&resolve),
+        };
+
+        self.skip_spaces();
+        match self.chars.peek() {
+            None | Some('\n') => {}
+            Some('#') => self.skip_comment(),
+            Some(c) => return Err(format!("unexpected {:?} after the value of {:?}", c, name)),
+        }
+        Ok(Some((name, value)))
+    }
+
+    fn name(&mut self) -> String {
+        let mut name = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if !(c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+                break;
+            }
+            name.push(c);
+            self.next();
+        }
+        name
+    }
+
+    fn unquoted(&mut self, resolve: &impl Fn(&str) -> String) -> String {
+        let mut value = String::new();
+        while let Some(&c) = self.chars.peek() {
+            match c {
+                '\n' => break,
+                '#' if value.ends_with(char::is_whitespace) => break,
+                '$' => {
+                    self.next();
+                    value.push_str(&self.interpolation(resolve));
+                }
+                _ => {
+                    value.push(c);
+                    self.next();
+                }
+            }
+        }
+        value.trim_end().to_owned()
+    }
+
+    fn single_quoted(&mut self) -> Result<String, String> {
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                None => return Err("unterminated single quote".to_owned()),
+                Some('\'') => return Ok(value),
+                Some(c) => value.push(c),
+            }
+        }
+    }
+
+    fn double_quoted(&mut self, resolve: &impl Fn(&str) -> String) -> Result<String, String> {
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                None => return Err("unterminated double quote".to_owned()),
+                Some('"') => return Ok(value),
+                Some('\\') => match self.next() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some(c @ ('\\' | '"' | '$')) => value.push(c),
+                    Some(c) => {
+                        value.push('\\');
+                        value.push(c);
+                    }
+                    None => return Err("unterminated double quote".to_owned()),
+                },
+                Some('$') => value.push_str(&self.interpolation(resolve)),
+                Some(c) => value.push(c),
+            }
+        }
+    }
+
+    // Resolves `$NAME` or `${NAME}`, just after the `$`. A `$` followed by
+    // anything else is kept as it is.
+    fn interpolation(&mut self, resolve: &impl Fn(&str) -> String) -> String {
+        if self.chars.peek() == Some(&'{') {
+            self.next();
+            let name = self.name();
+            if self.chars.peek() == Some(&'}') {
+                self.next();
+                resolve(&name)
+            } else {
+                format!("${{{}", name)
+            }
+        } else {
+            let name = self.name();
+            if name.is_empty() {
+                "$".to_owned()
+            } else {
+                resolve(&name)
+            }
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        while let Some(&c) = self.chars.peek() {
+            if c == '\n' {
+                break;
+            }
+            self.next();
+        }
+    }
+
+    // Skips spaces and tabs, but not newlines, returning whether there were
+    // any.
+    fn skip_spaces(&mut self) -> bool {
+        let mut skipped = false;
+        while let Some(' ' | '\t' | '\r') = self.chars.peek() {
+            self.next();
+            skipped = true;
+        }
+        skipped
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+        }
+        c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> Result<Vec<(String, String)>, String> {
+        let mut variables = Variables::new();
+        load(
+            contents,
+            &mut variables,
+            |name| match name {
+                "OVERRIDDEN" => Some("from the overrides".to_owned()),
+                _ => None,
+            },
+            |name| match name {
+                "OUTSIDE" | "OVERRIDDEN" => Some("from outside".to_owned()),
+                _ => None,
+            },
+        )?;
+        Ok(variables.into_iter().collect())
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parses_simple_assignments() {
+        let parsed = parse("ONE=1\nTWO = two words \n\nTHREE=\n");
+
+        assert_eq!(
+            parsed,
+            Ok(pairs(&[("ONE", "1"), ("THREE", ""), ("TWO", "two words")]))
+        );
+    }
+
+    #[test]
+    fn test_skips_comments() {
+        let parsed = parse("# a comment\nONE=1 # another comment\n  # indented\nTWO=a#b\n");
+
+        assert_eq!(parsed, Ok(pairs(&[("ONE", "1"), ("TWO", "a#b")])));
+    }
+
+    #[test]
+    fn test_allows_export() {
+        let parsed = parse("export ONE=1\nexport=2\n");
+
+        assert_eq!(parsed, Ok(pairs(&[("ONE", "1"), ("export", "2")])));
+    }
+
+    #[test]
+    fn test_takes_single_quotes_literally() {
+        let parsed = parse("ONE='$OUTSIDE \\n # not a comment'\nTWO='multiple\nlines'\n");
+
+        assert_eq!(
+            parsed,
+            Ok(pairs(&[
+                ("ONE", "$OUTSIDE \\n # not a comment"),
+                ("TWO", "multiple\nlines")
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_understands_escapes_in_double_quotes() {
+        let parsed = parse("ONE=\"a \\\"quote\\\"\\n\\ttab \\$ \\\\ # not a comment\" # comment\n");
+
+        assert_eq!(
+            parsed,
+            Ok(pairs(&[("ONE", "a \"quote\"\n\ttab $ \\ # not a comment")]))
+        );
+    }
+
+    #[test]
+    fn test_interpolates_variables() {
+        let parsed =
+            parse("ONE=1\nTWO=${ONE}2\nTHREE=\"$TWO-${OUTSIDE}\"\nFOUR=${MISSING}4\nFIVE=$ 5\n");
+
+        assert_eq!(
+            parsed,
+            Ok(pairs(&[
+                ("FIVE", "$ 5"),
+                ("FOUR", "4"),
+                ("ONE", "1"),
+                ("THREE", "12-from outside"),
+                ("TWO", "12"),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_prefers_overrides_when_interpolating() {
+        let parsed = parse("OVERRIDDEN=from the file\nONE=$OVERRIDDEN\n");
+
+        assert_eq!(
+            parsed,
+            Ok(pairs(&[
+                ("ONE", "from the overrides"),
+                ("OVERRIDDEN", "from the file"),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_reports_the_line_of_an_error() {
+        let parsed = parse("ONE=1\n\nTWO='unterminated\n");
+
+        assert_eq!(parsed, Err("line 3: unterminated single quote".to_owned()));
+    }
+
+    #[test]
+    fn test_rejects_a_line_without_an_assignment() {
+        let parsed = parse("ONE=1\nTWO\n");
+
+        assert_eq!(parsed, Err("line 2: expected `=` after \"TWO\"".to_owned()));
+    }
+}
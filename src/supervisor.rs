@@ -1,15 +1,59 @@
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
 
-use crate::communication::{ExitStatus, Start, Stop};
+mod persistence;
+
+use crate::communication::{
+    Exit, ExitStatus, List, Logs, ServiceDetails, ServiceState, Start, Stop, StopGroup,
+    StopSelected,
+};
 use crate::error::{DaemonError, DaemonResult};
+use crate::labels::Labels;
+use crate::log;
 use crate::names::{random_name, Name};
+use crate::output::{self, Output};
+use crate::processes;
+use crate::restart::RestartPolicy;
 use crate::services::*;
 use crate::timing::Duration;
+use crate::wait::WaitFor;
+
+use persistence::PersistedService;
+
+#[derive(Debug, Clone)]
+pub struct SupervisorOptions {
+    /// Where to keep state, such as service output and the table of services.
+    ///
+    /// If this is not set, service output is passed through to our own output,
+    /// and nothing is remembered if the supervisor crashes.
+    pub state_directory: Option<PathBuf>,
+    /// How long to wait for a service to be ready, if the request doesn't say.
+    pub start_timeout: Duration,
+    /// How long a service has to run before it is considered healthy, after
+    /// which its earlier restarts no longer count towards its restart policy.
+    pub stable_period: Duration,
+}
+
+impl Default for SupervisorOptions {
+    fn default() -> Self {
+        Self {
+            state_directory: None,
+            start_timeout: Duration::START_TIMEOUT,
+            stable_period: Duration::STABLE_PERIOD,
+        }
+    }
+}
 
 #[derive(Clone)]
-pub struct Supervisor(Arc<Mutex<RunningServices>>);
+pub struct Supervisor {
+    services: Arc<Mutex<RunningServices>>,
+    options: Arc<SupervisorOptions>,
+}
 
 impl Default for Supervisor {
     fn default() -> Self {
@@ -19,68 +63,941 @@ impl Default for Supervisor {
 
 impl Supervisor {
     pub fn new() -> Self {
-        Self(Arc::new(Mutex::new(RunningServices::new())))
+        Self::with_options(SupervisorOptions::default())
+    }
+
+    pub fn with_options(options: SupervisorOptions) -> Self {
+        let state_file = options
+            .state_directory
+            .as_ref()
+            .map(|directory| directory.join("services.json"));
+        Self {
+            services: Arc::new(Mutex::new(RunningServices::new(
+                state_file,
+                options.stable_period,
+            ))),
+            options: Arc::new(options),
+        }
+    }
+
+    /// Picks up the services left behind by a previous supervisor using the
+    /// same state directory, which probably crashed.
+    ///
+    /// Services that are still running are adopted; the rest are marked as
+    /// having exited.
+    pub fn restore(&self) -> DaemonResult<()> {
+        let mut inner = self.services.lock().unwrap();
+        let Some(state_file) = inner.state_file.clone() else {
+            return Ok(());
+        };
+        let persisted = persistence::read(&state_file)
+            .map_err(|error| DaemonError::RestoreStateError(error.into()))?;
+        for persisted in persisted {
+            let name = persisted.name.clone();
+            if inner.has_service_named(&name) {
+                continue;
+            }
+            let output = self.output_for(&name);
+            let supervised = SupervisedService::restore(&name, persisted, output)?;
+            log::info!(
+                event = "SERVICE_RESTORED",
+                name,
+                running = matches!(supervised.state, State::Running)
+            );
+            inner.add(name, supervised);
+        }
+        Ok(())
     }
 
     pub fn start(&self, instruction: &Start) -> DaemonResult<Name> {
-        let mut inner = self.0.lock().unwrap();
         let name = instruction.name.clone().unwrap_or_else(random_name);
-        if inner.has_service_named(&name) {
-            return Err(DaemonError::ServiceAlreadyExistsError { name });
+        let owner = instruction
+            .owner
+            .map(|process_id| {
+                processes::start_time(process_id)
+                    .map(|start_time| Owner {
+                        process_id,
+                        start_time,
+                    })
+                    .ok_or(DaemonError::OwnerNotRunningError)
+            })
+            .transpose()?;
+        // we reserve the name up front, and then do the slow work without
+        // holding the lock, so that we don't hold up anyone else
+        self.services.lock().unwrap().reserve(&name)?;
+        let output = self.output_for(&name);
+        let timeout = instruction.timeout.unwrap_or(self.options.start_timeout);
+        let start_time = Instant::now();
+        // we get ready to wait before starting, so that we don't miss anything
+        let started = output
+            .clear()
+            .map_err(|error| DaemonError::CaptureOutputError(error.into()))
+            .and_then(|()| instruction.wait.prepare(&output))
+            .and_then(|waiter| Ok((waiter, instruction.service.start(&output, timeout)?)));
+        let (waiter, running) = match started {
+            Ok(started) => started,
+            Err(error) => {
+                self.services.lock().unwrap().release(&name);
+                return Err(error);
+            }
+        };
+        let mut inner = self.services.lock().unwrap();
+        if inner.shutting_down {
+            // everything else has already been stopped, so we stop this too
+            inner.release(&name);
+            drop(inner);
+            let mut running = running;
+            running.stop(Duration::STOP_TIMEOUT)?;
+            return Err(DaemonError::ShuttingDownError);
         }
-        let running = instruction.service.start()?;
-        let running = inner.add(name.clone(), running);
-        instruction.wait.block_until_ready(Duration::FOREVER)?; // we need to pick a global timeout here
-        if running.is_running()? {
-            Ok(name)
-        } else {
-            Err(DaemonError::ServiceCrashedError)
+        let sequence = inner.add(
+            name.clone(),
+            SupervisedService {
+                service: instruction.service.clone(),
+                output: output.clone(),
+                wait: instruction.wait.clone(),
+                timeout,
+                restart: instruction.restart.clone(),
+                start_time: chrono::Utc::now(),
+                running,
+                state: State::Starting,
+                restarts: 0,
+                last_exit: None,
+                owner,
+                group: instruction.group.clone(),
+                labels: instruction.labels.clone(),
+                // assigned when the service is added
+                sequence: 0,
+            },
+        );
+        drop(inner);
+
+        // preparing the service, such as realising a Nix environment, counts
+        // towards the timeout
+        let remaining = timeout.saturating_sub(start_time.elapsed().into());
+        let ready = waiter.block_until_ready(remaining, || {
+            self.services.lock().unwrap().has_exited(&name, sequence)
+        });
+
+        // if someone stopped it while we were waiting, another service may
+        // have been started with the same name, so we check it's still ours
+        let mut inner = self.services.lock().unwrap();
+        match ready {
+            // if it exited, we record that below
+            Ok(()) | Err(DaemonError::ServiceCrashedError) => {}
+            Err(error) => {
+                // the service never became ready, so we don't keep it around,
+                // but it's the reason it didn't start that matters
+                if let Some(supervised) = inner.take_started(&name, sequence) {
+                    drop(inner);
+                    if let Err(stop_error) = self.stop_taken(&name, supervised) {
+                        log::error!(event = "SERVICE_STOPPED", name, error = stop_error);
+                    }
+                }
+                return Err(error);
+            }
         }
+        let Some(supervised) = inner.get_started(&name, sequence) else {
+            return Err(DaemonError::NoSuchServiceError { name });
+        };
+        let result = match supervised.running.exit_status()? {
+            None => {
+                supervised.state = State::Running;
+                Ok(name)
+            }
+            Some(status) => {
+                // if it crashes immediately, we don't try to restart it
+                supervised.record_exit(&name, status);
+                supervised.state = State::Exited;
+                Err(DaemonError::ServiceCrashedError)
+            }
+        };
+        inner.persist();
+        result
     }
 
     pub fn stop(&self, instruction: &Stop) -> DaemonResult<ExitStatus> {
-        let mut inner = self.0.lock().unwrap();
         let name = &instruction.name;
-        match inner.retrieve(name) {
-            Some(mut service) => service.stop(Duration::STOP_TIMEOUT),
+        let supervised = self.services.lock().unwrap().take(name);
+        match supervised {
+            Some(supervised) => self.stop_taken(name, supervised),
             None => Err(DaemonError::NoSuchServiceError { name: name.clone() }),
         }
     }
 
+    /// Stops every service in the group, most recently started first.
+    ///
+    /// If the group has no services, there is nothing to do, so this succeeds.
+    pub fn stop_group(&self, instruction: &StopGroup) -> DaemonResult<Vec<(Name, ExitStatus)>> {
+        let members = self
+            .services
+            .lock()
+            .unwrap()
+            .take_where(|supervised| supervised.group.as_ref() == Some(&instruction.group));
+        self.stop_many(members)
+    }
+
+    /// Stops every service matching the selector, most recently started
+    /// first.
+    pub fn stop_selected(
+        &self,
+        instruction: &StopSelected,
+    ) -> DaemonResult<Vec<(Name, ExitStatus)>> {
+        let members = self
+            .services
+            .lock()
+            .unwrap()
+            .take_where(|supervised| instruction.selector.matches(&supervised.labels));
+        self.stop_many(members)
+    }
+
+    fn stop_many(
+        &self,
+        members: Vec<(Name, SupervisedService)>,
+    ) -> DaemonResult<Vec<(Name, ExitStatus)>> {
+        // we carry on if one fails to stop, so that we don't leave the rest
+        // running
+        let mut result = Ok(Vec::with_capacity(members.len()));
+        for (name, supervised) in members {
+            let stopped = self.stop_taken(&name, supervised);
+            result = match (result, stopped) {
+                (Ok(mut exit_statuses), Ok(exit_status)) => {
+                    exit_statuses.push((name, exit_status));
+                    Ok(exit_statuses)
+                }
+                (Err(error), _) | (_, Err(error)) => Err(error),
+            };
+        }
+        result
+    }
+
+    // Stops a service that has been taken out of the map, without holding the
+    // lock, and then frees up its name.
+    fn stop_taken(
+        &self,
+        name: &Name,
+        mut supervised: SupervisedService,
+    ) -> DaemonResult<ExitStatus> {
+        let process_id = supervised.running.process_id();
+        // the name must be released, even if stopping panics
+        let result = joined(
+            process_id,
+            panic::catch_unwind(AssertUnwindSafe(|| {
+                supervised.running.stop(Duration::STOP_TIMEOUT)
+            })),
+        );
+        self.services.lock().unwrap().release(name);
+        result
+    }
+
+    pub fn list(&self) -> DaemonResult<Vec<ServiceDetails>> {
+        self.services.lock().unwrap().list()
+    }
+
+    /// Lists the services matching the selector.
+    pub fn list_selected(&self, instruction: &List) -> DaemonResult<Vec<ServiceDetails>> {
+        let mut details = self.list()?;
+        details.retain(|details| instruction.selector.matches(&details.labels));
+        Ok(details)
+    }
+
+    /// Checks whether there is nothing left to supervise: no service is
+    /// starting, running, or waiting to restart.
+    pub fn is_idle(&self) -> DaemonResult<bool> {
+        self.services.lock().unwrap().is_idle()
+    }
+
+    /// Sends the output of a service so far, a chunk at a time.
+    pub fn logs(
+        &self,
+        instruction: &Logs,
+        mut send: impl FnMut(&[u8]) -> DaemonResult<()>,
+    ) -> DaemonResult<()> {
+        let output = self.output_for(&instruction.name);
+        let path = output
+            .path(instruction.stream)
+            .ok_or(DaemonError::OutputNotCapturedError)?;
+        // the output is kept around after the service stops, so we don't
+        // check whether the service is still supervised
+        let chunks = output::read(path, instruction.tail).map_err(|error| match error.kind() {
+            std::io::ErrorKind::NotFound => DaemonError::NoSuchServiceError {
+                name: instruction.name.clone(),
+            },
+            _ => DaemonError::ReadOutputError(error.into()),
+        })?;
+        for chunk in chunks {
+            send(&chunk.map_err(|error| DaemonError::ReadOutputError(error.into()))?)?;
+        }
+        Ok(())
+    }
+
+    /// Sends the output of a service as it is written, until the service stops.
+    ///
+    /// If there's no new output for a while, `send` is called with nothing, so
+    /// that the caller can check whether anyone is still listening.
+    pub fn follow_logs(
+        &self,
+        instruction: &Logs,
+        mut send: impl FnMut(&[u8]) -> DaemonResult<()>,
+    ) -> DaemonResult<()> {
+        let output = self.output_for(&instruction.name);
+        let path = output
+            .path(instruction.stream)
+            .ok_or(DaemonError::OutputNotCapturedError)?;
+        let mut follower =
+            output::Follower::open(path, instruction.tail).map_err(|error| match error.kind() {
+                std::io::ErrorKind::NotFound => DaemonError::NoSuchServiceError {
+                    name: instruction.name.clone(),
+                },
+                _ => DaemonError::ReadOutputError(error.into()),
+            })?;
+        let mut last_sent = Instant::now();
+        loop {
+            // we check before reading, so we don't miss anything written just
+            // before the service stopped
+            let active = self.services.lock().unwrap().is_active(&instruction.name)?;
+            for chunk in follower.read_more() {
+                send(&chunk.map_err(|error| DaemonError::ReadOutputError(error.into()))?)?;
+                last_sent = Instant::now();
+            }
+            if last_sent.elapsed() >= Duration::HEARTBEAT.into() {
+                send(&[])?;
+                last_sent = Instant::now();
+            }
+            if !active {
+                return Ok(());
+            }
+            Duration::QUANTUM.sleep();
+        }
+    }
+
+    /// Checks on every service, recording any that have exited, restarting
+    /// them if necessary, and stopping any whose owner has gone away.
+    pub fn reap(&self) -> DaemonResult<()> {
+        let (result, due, orphaned) = {
+            let mut inner = self.services.lock().unwrap();
+            (
+                inner.reap(),
+                inner.take_due_restarts(),
+                inner.take_orphaned(),
+            )
+        };
+        // restarting can be slow, so we don't hold up checking on everything
+        // else
+        for restart in due {
+            let supervisor = self.clone();
+            thread::spawn(move || supervisor.restart(restart));
+        }
+        // neither is stopping, so we stop the orphaned services in the
+        // background; their names stay reserved until they have stopped
+        for (name, supervised) in orphaned {
+            log::info!(event = "OWNER_EXITED", name);
+            let supervisor = self.clone();
+            thread::spawn(move || {
+                if let Err(error) = supervisor.stop_taken(&name, supervised) {
+                    log::error!(event = "SERVICE_STOPPED", name, error);
+                }
+            });
+        }
+        result
+    }
+
+    // Relaunches a service, without holding the lock while it starts.
+    fn restart(&self, restart: DueRestart) {
+        let DueRestart {
+            name,
+            sequence,
+            service,
+            output,
+            wait,
+            timeout,
+        } = restart;
+        let start_time = Instant::now();
+        // the restart must be counted as finished, even if starting panics,
+        // or we'd wait for it forever when shutting down
+        let started = panic::catch_unwind(AssertUnwindSafe(|| {
+            wait.prepare(&output)
+                .and_then(|waiter| Ok((waiter, service.start(&output, timeout)?)))
+        }))
+        .unwrap_or_else(|_| {
+            Err(DaemonError::StartProcessError(
+                std::io::Error::new(std::io::ErrorKind::Other, "panicked while starting").into(),
+            ))
+        });
+        let mut inner = self.services.lock().unwrap();
+        inner.restarting -= 1;
+        let Some(supervised) = inner.get_started(&name, sequence) else {
+            // someone stopped it while we were starting it
+            drop(inner);
+            if let Ok((_, mut running)) = started {
+                if let Err(error) = running.stop(Duration::STOP_TIMEOUT) {
+                    log::error!(event = "SERVICE_RESTARTED", name, error);
+                }
+            }
+            return;
+        };
+        let waiter = match started {
+            Ok((waiter, running)) => {
+                supervised.running = running;
+                supervised.state = State::Starting;
+                supervised.start_time = chrono::Utc::now();
+                supervised.restarts += 1;
+                log::info!(
+                    event = "SERVICE_RESTARTED",
+                    name,
+                    restarts = supervised.restarts
+                );
+                waiter
+            }
+            Err(error) => {
+                supervised.state = State::Exited;
+                log::error!(event = "SERVICE_RESTARTED", name, error);
+                inner.persist();
+                return;
+            }
+        };
+        inner.persist();
+        drop(inner);
+
+        let remaining = timeout.saturating_sub(start_time.elapsed().into());
+        let ready = waiter.block_until_ready(remaining, || {
+            self.services.lock().unwrap().has_exited(&name, sequence)
+        });
+
+        let mut inner = self.services.lock().unwrap();
+        let stable_period = inner.stable_period;
+        let Some(supervised) = inner.get_started(&name, sequence) else {
+            // someone stopped it while we were waiting
+            return;
+        };
+        let exited = supervised.running.exit_status().unwrap_or_else(|error| {
+            log::error!(event = "SERVICE_READY", name, error);
+            None
+        });
+        match (ready, exited) {
+            (ready, Some(status)) => {
+                if let Err(error) = ready {
+                    log::warning!(event = "SERVICE_READY", name, error);
+                }
+                supervised.exited(&name, status, stable_period);
+            }
+            (Ok(()), None) => {
+                log::info!(event = "SERVICE_READY", name);
+                supervised.state = State::Running;
+            }
+            (Err(error), None) => {
+                log::warning!(event = "SERVICE_READY", name, error);
+                // it never became ready, so we stop it, and then treat it like
+                // any other failure
+                let Some(mut supervised) = inner.take_started(&name, sequence) else {
+                    return;
+                };
+                drop(inner);
+                let stopped = supervised.running.stop(Duration::STOP_TIMEOUT);
+                inner = self.services.lock().unwrap();
+                match stopped {
+                    Ok(status) => supervised.exited(&name, status, stable_period),
+                    Err(error) => {
+                        log::error!(event = "SERVICE_STOPPED", name, error);
+                        supervised.state = State::Exited;
+                    }
+                }
+                inner.put_back(name, supervised);
+            }
+        }
+        inner.persist();
+    }
+
+    /// Stops every service, and refuses to start any more.
+    ///
+    /// The services are stopped in parallel. We also wait for any that are
+    /// still starting, as they will notice and stop themselves.
     pub fn stop_all(&self) -> DaemonResult<()> {
-        self.0.lock().unwrap().stop_all()
+        let services = {
+            let mut inner = self.services.lock().unwrap();
+            inner.shutting_down = true;
+            inner.take_where(|_| true)
+        };
+        let stopped = thread::scope(|scope| {
+            services
+                .into_iter()
+                .map(|(name, supervised)| {
+                    let process_id = supervised.running.process_id();
+                    (
+                        process_id,
+                        scope.spawn(move || self.stop_taken(&name, supervised)),
+                    )
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .map(|(process_id, handle)| joined(process_id, handle.join()))
+                .collect::<Vec<DaemonResult<ExitStatus>>>()
+        });
+        while self.services.lock().unwrap().has_pending_starts() {
+            Duration::QUANTUM.sleep();
+        }
+        stopped
+            .into_iter()
+            .collect::<DaemonResult<Vec<ExitStatus>>>()
+            .map(|_| ())
+    }
+
+    fn output_for(&self, name: &Name) -> Output {
+        match &self.options.state_directory {
+            None => Output::Inherit,
+            Some(directory) => Output::in_directory(&directory.join("logs"), name),
+        }
+    }
+}
+
+// Turns a panic while stopping a service into an error, so that it doesn't
+// take the daemon down with it.
+fn joined(
+    process_id: u32,
+    result: thread::Result<DaemonResult<ExitStatus>>,
+) -> DaemonResult<ExitStatus> {
+    result.unwrap_or_else(|_| {
+        Err(DaemonError::StopProcessError {
+            process_id,
+            inner: std::io::Error::new(std::io::ErrorKind::Other, "panicked while stopping").into(),
+        })
+    })
+}
+
+struct SupervisedService {
+    service: Service,
+    output: Output,
+    wait: WaitFor,
+    timeout: Duration,
+    restart: RestartPolicy,
+    start_time: chrono::DateTime<chrono::Utc>,
+    running: RunningService,
+    state: State,
+    restarts: u32,
+    last_exit: Option<Exit>,
+    owner: Option<Owner>,
+    group: Option<Name>,
+    labels: Labels,
+    // Orders the services by when they were first started.
+    sequence: u64,
+}
+
+/// The process that owns a service, identified by its process ID and start
+/// time in case the process ID is reused.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+struct Owner {
+    process_id: u32,
+    start_time: u64,
+}
+
+impl Owner {
+    fn is_running(&self) -> bool {
+        processes::is_running(self.process_id, self.start_time)
+    }
+}
+
+enum State {
+    Starting,
+    Running,
+    Exited,
+    Restarting { at: Instant },
+}
+
+impl State {
+    fn public(&self) -> ServiceState {
+        match self {
+            Self::Starting => ServiceState::Starting,
+            Self::Running => ServiceState::Running,
+            Self::Exited => ServiceState::Exited,
+            Self::Restarting { .. } => ServiceState::Restarting,
+        }
+    }
+}
+
+impl SupervisedService {
+    fn persisted(&self, name: &Name) -> PersistedService {
+        PersistedService {
+            name: name.clone(),
+            service: self.service.clone(),
+            process_id: self.running.process_id(),
+            process_start_time: self.running.start_time(),
+            start_time: self.start_time,
+            wait: self.wait.clone(),
+            timeout: self.timeout,
+            restart: self.restart.clone(),
+            running: !matches!(self.state, State::Exited),
+            restarts: self.restarts,
+            last_exit: self.last_exit.clone(),
+            owner: self.owner.clone(),
+            group: self.group.clone(),
+            labels: self.labels.clone(),
+        }
+    }
+
+    fn restore(name: &Name, persisted: PersistedService, output: Output) -> DaemonResult<Self> {
+        let mut supervised = Self {
+            running: persisted
+                .service
+                .adopt(persisted.process_id, persisted.process_start_time),
+            service: persisted.service,
+            output,
+            wait: persisted.wait,
+            timeout: persisted.timeout,
+            restart: persisted.restart,
+            start_time: persisted.start_time,
+            state: State::Exited,
+            restarts: persisted.restarts,
+            last_exit: persisted.last_exit,
+            owner: persisted.owner,
+            group: persisted.group,
+            labels: persisted.labels,
+            // assigned when the service is added
+            sequence: 0,
+        };
+        if persisted.running {
+            match supervised.running.exit_status()? {
+                None => {
+                    supervised.state = State::Running;
+                }
+                Some(status) => {
+                    // it stopped while nobody was watching
+                    supervised.record_exit(name, status);
+                }
+            }
+        }
+        Ok(supervised)
+    }
+
+    // Records the exit status if the service has stopped since we last checked.
+    fn refresh(&mut self, name: &Name, stable_period: Duration) -> DaemonResult<()> {
+        if let State::Running = self.state {
+            if let Some(status) = self.running.exit_status()? {
+                self.exited(name, status, stable_period);
+            }
+        }
+        Ok(())
+    }
+
+    // Records that the service has stopped, and schedules a restart if the
+    // restart policy calls for one.
+    fn exited(&mut self, name: &Name, status: ExitStatus, stable_period: Duration) {
+        // if it was healthy for long enough, we start counting again
+        let ran_for = (chrono::Utc::now() - self.start_time).to_std();
+        if ran_for.map_or(false, |ran_for| ran_for >= stable_period.into()) {
+            self.restarts = 0;
+        }
+        let exit = self.record_exit(name, status);
+        self.state = match self
+            .restart
+            .delay_before_restart(&exit.status, self.restarts)
+        {
+            None => State::Exited,
+            Some(delay) => {
+                log::info!(event = "SERVICE_RESTARTING", name, delay);
+                State::Restarting {
+                    at: Instant::now() + std::time::Duration::from(delay),
+                }
+            }
+        };
+    }
+
+    fn record_exit(&mut self, name: &Name, status: ExitStatus) -> Exit {
+        let exit = Exit {
+            status,
+            time: chrono::Utc::now(),
+        };
+        log::warning!(event = "SERVICE_EXITED", name, exit);
+        self.last_exit = Some(exit.clone());
+        exit
+    }
+
+    fn is_due_for_restart(&self) -> bool {
+        matches!(self.state, State::Restarting { at } if at <= Instant::now())
     }
 }
 
-struct RunningServices(HashMap<Name, RunningService>);
+// What we need to restart a service without holding the lock.
+struct DueRestart {
+    name: Name,
+    sequence: u64,
+    service: Service,
+    output: Output,
+    wait: WaitFor,
+    timeout: Duration,
+}
+
+struct RunningServices {
+    services: HashMap<Name, SupervisedService>,
+    // Names of services that are starting or stopping, and so cannot be used.
+    reserved: HashSet<Name>,
+    // Where we remember the services, and what we last wrote there.
+    state_file: Option<PathBuf>,
+    persisted: Vec<u8>,
+    next_sequence: u64,
+    // How many services are being restarted outside the lock.
+    restarting: usize,
+    // How long a service has to run before its restarts are forgotten.
+    stable_period: Duration,
+    // Set once we start shutting down, after which nothing else can start.
+    shutting_down: bool,
+}
 
 impl RunningServices {
-    fn new() -> Self {
-        Self(HashMap::new())
+    fn new(state_file: Option<PathBuf>, stable_period: Duration) -> Self {
+        Self {
+            services: HashMap::new(),
+            reserved: HashSet::new(),
+            state_file,
+            persisted: Vec::new(),
+            next_sequence: 0,
+            restarting: 0,
+            stable_period,
+            shutting_down: false,
+        }
     }
 
     fn has_service_named(&self, name: &Name) -> bool {
-        self.0.contains_key(name)
+        self.services.contains_key(name) || self.reserved.contains(name)
+    }
+
+    fn reserve(&mut self, name: &Name) -> DaemonResult<()> {
+        if self.shutting_down {
+            return Err(DaemonError::ShuttingDownError);
+        }
+        if self.has_service_named(name) {
+            return Err(DaemonError::ServiceAlreadyExistsError { name: name.clone() });
+        }
+        self.reserved.insert(name.clone());
+        Ok(())
+    }
+
+    fn release(&mut self, name: &Name) {
+        self.reserved.remove(name);
+    }
+
+    // Checks whether anything is starting, or restarting, outside the lock.
+    fn has_pending_starts(&self) -> bool {
+        !self.reserved.is_empty() || self.restarting > 0
     }
 
-    fn add(&mut self, name: Name, service: RunningService) -> &mut RunningService {
-        match self.0.entry(name) {
+    // Adds a service, releasing its reservation, and returns the sequence
+    // number that identifies it.
+    fn add(&mut self, name: Name, mut service: SupervisedService) -> u64 {
+        self.reserved.remove(&name);
+        let sequence = self.next_sequence;
+        service.sequence = sequence;
+        self.next_sequence += 1;
+        match self.services.entry(name) {
             Entry::Occupied(_) => unreachable!("The service name was stolen."),
-            Entry::Vacant(entry) => entry.insert(service),
+            Entry::Vacant(entry) => {
+                entry.insert(service);
+            }
+        }
+        self.persist();
+        sequence
+    }
+
+    // Finds a service, as long as it's the one we added with this sequence
+    // number, and not another started later with the same name.
+    fn get_started(&mut self, name: &Name, sequence: u64) -> Option<&mut SupervisedService> {
+        self.services
+            .get_mut(name)
+            .filter(|supervised| supervised.sequence == sequence)
+    }
+
+    // Removes a service, as long as it's the one we added with this sequence
+    // number, keeping its name reserved until it is released.
+    fn take_started(&mut self, name: &Name, sequence: u64) -> Option<SupervisedService> {
+        self.get_started(name, sequence)?;
+        self.take(name)
+    }
+
+    // Checks whether a service has exited, or been stopped and removed.
+    fn has_exited(&mut self, name: &Name, sequence: u64) -> DaemonResult<bool> {
+        match self.get_started(name, sequence) {
+            None => Ok(true),
+            Some(supervised) => Ok(supervised.running.exit_status()?.is_some()),
+        }
+    }
+
+    // Puts back a service that was taken, keeping its place in the order, and
+    // releases its name.
+    fn put_back(&mut self, name: Name, supervised: SupervisedService) {
+        self.reserved.remove(&name);
+        self.services.insert(name, supervised);
+        self.persist();
+    }
+
+    // Removes a service, keeping its name reserved until it is released.
+    fn take(&mut self, name: &Name) -> Option<SupervisedService> {
+        let supervised = self.services.remove(name)?;
+        self.reserved.insert(name.clone());
+        self.persist();
+        Some(supervised)
+    }
+
+    // Writes the services to the state file, if anything has changed.
+    fn persist(&mut self) {
+        let Some(state_file) = &self.state_file else {
+            return;
+        };
+        // we keep them in the order they were started, so that they are
+        // restored in the same order
+        let mut services = self.services.iter().collect::<Vec<_>>();
+        services.sort_by_key(|(_, supervised)| supervised.sequence);
+        let persisted = services
+            .into_iter()
+            .map(|(name, supervised)| supervised.persisted(name))
+            .collect::<Vec<PersistedService>>();
+        let result = persistence::serialize(&persisted).and_then(|contents| {
+            if contents != self.persisted {
+                persistence::write(state_file, &contents)?;
+                self.persisted = contents;
+            }
+            Ok(())
+        });
+        if let Err(error) = result {
+            log::error!(event = "PERSIST", error = error.log());
+        }
+    }
+
+    // Checks whether the service is running, or will be running again soon.
+    fn is_active(&mut self, name: &Name) -> DaemonResult<bool> {
+        match self.services.get_mut(name) {
+            None => Ok(false),
+            Some(supervised) => {
+                supervised.refresh(name, self.stable_period)?;
+                Ok(!matches!(supervised.state, State::Exited))
+            }
+        }
+    }
+
+    fn is_idle(&mut self) -> DaemonResult<bool> {
+        if self.has_pending_starts() {
+            return Ok(false);
+        }
+        let names = self.services.keys().cloned().collect::<Vec<Name>>();
+        for name in names {
+            if self.is_active(&name)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn list(&mut self) -> DaemonResult<Vec<ServiceDetails>> {
+        let mut details = self
+            .services
+            .iter_mut()
+            .map(|(name, supervised)| {
+                supervised.refresh(name, self.stable_period)?;
+                Ok(ServiceDetails {
+                    name: name.clone(),
+                    service: supervised.service.clone(),
+                    process_id: supervised.running.process_id(),
+                    start_time: supervised.start_time,
+                    wait: supervised.wait.clone(),
+                    state: supervised.state.public(),
+                    restarts: supervised.restarts,
+                    last_exit: supervised.last_exit.clone(),
+                    owner: supervised.owner.as_ref().map(|owner| owner.process_id),
+                    group: supervised.group.clone(),
+                    labels: supervised.labels.clone(),
+                })
+            })
+            .collect::<DaemonResult<Vec<ServiceDetails>>>()?;
+        details.sort_by(|a, b| (a.start_time, &a.name).cmp(&(b.start_time, &b.name)));
+        Ok(details)
+    }
+
+    fn reap(&mut self) -> DaemonResult<()> {
+        let result = self
+            .services
+            .iter_mut()
+            .map(|(name, supervised)| supervised.refresh(name, self.stable_period))
+            .collect::<Vec<DaemonResult<()>>>()
+            .into_iter()
+            .collect::<DaemonResult<()>>();
+        self.persist();
+        result
+    }
+
+    // Finds every service that is due to be restarted, marking them as
+    // starting so that they are only restarted once.
+    fn take_due_restarts(&mut self) -> Vec<DueRestart> {
+        if self.shutting_down {
+            return Vec::new();
         }
+        let due = self
+            .services
+            .iter_mut()
+            .filter(|(_, supervised)| supervised.is_due_for_restart())
+            .map(|(name, supervised)| {
+                supervised.state = State::Starting;
+                DueRestart {
+                    name: name.clone(),
+                    sequence: supervised.sequence,
+                    service: supervised.service.clone(),
+                    output: supervised.output.clone(),
+                    wait: supervised.wait.clone(),
+                    timeout: supervised.timeout,
+                }
+            })
+            .collect::<Vec<DueRestart>>();
+        self.restarting += due.len();
+        due
+    }
+
+    // Removes every service that matches, most recently started first,
+    // keeping their names reserved until they are released.
+    fn take_where(
+        &mut self,
+        predicate: impl Fn(&SupervisedService) -> bool,
+    ) -> Vec<(Name, SupervisedService)> {
+        let mut members = self
+            .services
+            .iter()
+            .filter(|(_, supervised)| predicate(supervised))
+            .map(|(name, supervised)| (supervised.sequence, name.clone()))
+            .collect::<Vec<(u64, Name)>>();
+        members.sort_by(|a, b| b.cmp(a));
+        members
+            .into_iter()
+            .filter_map(|(_, name)| self.take(&name).map(|supervised| (name, supervised)))
+            .collect()
     }
 
-    fn retrieve(&mut self, name: &Name) -> Option<RunningService> {
-        self.0.remove(name)
+    // Removes every service whose owner is no longer running, keeping their
+    // names reserved until they are released.
+    fn take_orphaned(&mut self) -> Vec<(Name, SupervisedService)> {
+        let orphaned = self
+            .services
+            .iter()
+            .filter(|(_, supervised)| {
+                supervised
+                    .owner
+                    .as_ref()
+                    .map_or(false, |owner| !owner.is_running())
+            })
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<Name>>();
+        orphaned
+            .into_iter()
+            .filter_map(|name| self.take(&name).map(|supervised| (name, supervised)))
+            .collect()
     }
 
+    // Stops everything that's left, one at a time.
     fn stop_all(&mut self) -> DaemonResult<()> {
-        self.0
+        let result = self
+            .services
             .drain()
-            .map(|(_, mut service)| service.stop(Duration::STOP_TIMEOUT).map(|_| ()))
+            .map(|(_, mut supervised)| supervised.running.stop(Duration::STOP_TIMEOUT).map(|_| ()))
             .collect::<Vec<DaemonResult<()>>>()
             .into_iter()
-            .collect::<DaemonResult<()>>()
+            .collect::<DaemonResult<()>>();
+        self.persist();
+        result
     }
 }
 
@@ -94,13 +1011,25 @@ impl Drop for RunningServices {
 mod tests {
     use std::fs;
 
+    use crate::output::Stream;
     use crate::ports::Port;
     use crate::test_helpers::*;
+    use crate::test_programs;
     use crate::test_services;
+    use crate::timing::DurationUnit;
     use crate::wait::WaitFor;
 
     use super::*;
 
+    fn read_logs(supervisor: &Supervisor, instruction: Logs) -> DaemonResult<Vec<u8>> {
+        let mut output = Vec::new();
+        supervisor.logs(&instruction, |chunk| {
+            output.extend_from_slice(chunk);
+            Ok(())
+        })?;
+        Ok(output)
+    }
+
     #[test]
     fn test_starts_a_single_service() -> anyhow::Result<()> {
         let output_directory = tempfile::tempdir()?;
@@ -108,9 +1037,8 @@ mod tests {
 
         let supervisor = Supervisor::new();
         supervisor.start(&Start {
-            name: None,
             service: test_services::file_watch(&output_file, vec!["echo".into(), "output".into()]),
-            wait: WaitFor::AMoment,
+            ..Default::default()
         })?;
 
         eventually(|| {
@@ -124,9 +1052,9 @@ mod tests {
         let service_port = Port::next_available()?;
         let supervisor = Supervisor::new();
         supervisor.start(&Start {
-            name: None,
             service: test_services::http_hello_world(service_port),
             wait: WaitFor::Port { port: service_port },
+            ..Default::default()
         })?;
 
         let response_body =
@@ -140,19 +1068,176 @@ mod tests {
     fn test_starts_a_single_service_and_waits_a_little() -> anyhow::Result<()> {
         let supervisor = Supervisor::new();
         let result = supervisor.start(&Start {
-            name: None,
             service: Service::Program(Program {
                 command: "true".into(),
-                arguments: Default::default(),
-                environment: Default::default(),
+                ..Default::default()
             }),
-            wait: WaitFor::AMoment,
+            ..Default::default()
         });
 
         assert_eq!(result, Err(DaemonError::ServiceCrashedError));
         Ok(())
     }
 
+    #[test]
+    fn test_stops_and_removes_a_service_that_is_not_ready_in_time() -> anyhow::Result<()> {
+        let name: Name = "slow".parse()?;
+        let service_port = Port::next_available()?;
+        let supervisor = Supervisor::new();
+        let result = supervisor.start(&Start {
+            name: Some(name.clone()),
+            service: test_services::http_hello_world(service_port),
+            wait: WaitFor::Port {
+                port: Port::next_available()?,
+            },
+            timeout: Some(Duration::of(500, DurationUnit::Milliseconds)),
+            ..Default::default()
+        });
+
+        assert!(
+            matches!(result, Err(DaemonError::TimeOut(_))),
+            "Expected a timeout but got {:?}",
+            result
+        );
+        assert_eq!(supervisor.list()?, vec![]);
+        eventually(|| test_eq(service_port.is_available(), true))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_uses_the_default_start_timeout() -> anyhow::Result<()> {
+        let supervisor = Supervisor::with_options(SupervisorOptions {
+            start_timeout: Duration::of(500, DurationUnit::Milliseconds),
+            ..Default::default()
+        });
+        let start_time = Instant::now();
+        let result = supervisor.start(&Start {
+            service: Service::Program(test_programs::waits_for_termination()),
+            wait: WaitFor::Time {
+                duration: Duration::of(10, DurationUnit::Seconds),
+            },
+            ..Default::default()
+        });
+
+        let elapsed = Instant::now() - start_time;
+        assert!(
+            matches!(result, Err(DaemonError::TimeOut(_))),
+            "Expected a timeout but got {:?}",
+            result
+        );
+        assert!(
+            elapsed < std::time::Duration::from_secs(5),
+            "Expected the elapsed time of {:?} to be approximately 500ms.",
+            elapsed
+        );
+        assert_eq!(supervisor.list()?, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_starts_services_concurrently() -> anyhow::Result<()> {
+        let supervisor = Supervisor::new();
+        let start = Start {
+            service: Service::Program(test_programs::waits_for_termination()),
+            wait: WaitFor::Time {
+                duration: Duration::of(1, DurationUnit::Seconds),
+            },
+            ..Default::default()
+        };
+
+        let start_time = Instant::now();
+        thread::scope(|scope| {
+            let handles = (0..4)
+                .map(|_| scope.spawn(|| supervisor.start(&start)))
+                .collect::<Vec<_>>();
+            // we can still do other things while they're starting
+            Duration::QUANTUM.sleep();
+            let listed = supervisor.list()?;
+            let listed_time = Instant::now();
+            for handle in handles {
+                handle.join().unwrap()?;
+            }
+            let end_time = Instant::now();
+
+            assert_eq!(listed.len(), 4);
+            assert!(
+                listed_time - start_time < std::time::Duration::from_millis(500),
+                "Expected listing the services not to wait for them to start.",
+            );
+            assert!(
+                end_time - start_time < std::time::Duration::from_millis(1900),
+                "Expected the services to start in parallel, but it took {:?}.",
+                end_time - start_time
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_reserves_the_name_of_a_service_while_it_is_starting() -> anyhow::Result<()> {
+        let name: Name = "reserved".parse()?;
+        let supervisor = Supervisor::new();
+        let start = Start {
+            name: Some(name.clone()),
+            service: Service::Program(test_programs::waits_for_termination()),
+            wait: WaitFor::Time {
+                duration: Duration::of(500, DurationUnit::Milliseconds),
+            },
+            ..Default::default()
+        };
+
+        thread::scope(|scope| {
+            let first = scope.spawn(|| supervisor.start(&start));
+            Duration::QUANTUM.sleep();
+            let second = supervisor.start(&start);
+            first.join().unwrap()?;
+
+            assert_eq!(second, Err(DaemonError::ServiceAlreadyExistsError { name }));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_does_not_mistake_a_replacement_for_the_service_it_is_starting() -> anyhow::Result<()> {
+        let name: Name = "replaced".parse()?;
+        let supervisor = Supervisor::new();
+        let slow_start = Start {
+            name: Some(name.clone()),
+            service: Service::Program(test_programs::waits_for_termination()),
+            wait: WaitFor::Time {
+                duration: Duration::of(500, DurationUnit::Milliseconds),
+            },
+            ..Default::default()
+        };
+        let quick_start = Start {
+            name: Some(name.clone()),
+            service: Service::Program(test_programs::waits_for_termination()),
+            ..Default::default()
+        };
+
+        thread::scope(|scope| {
+            let first = scope.spawn(|| supervisor.start(&slow_start));
+            Duration::QUANTUM.sleep();
+            supervisor.stop(&Stop { name: name.clone() })?;
+            supervisor.start(&quick_start)?;
+            let first_result = first.join().unwrap();
+
+            assert_eq!(
+                first_result,
+                Err(DaemonError::NoSuchServiceError { name: name.clone() })
+            );
+            let details = supervisor.list()?;
+            assert_eq!(
+                details
+                    .iter()
+                    .map(|details| (details.name.clone(), details.state))
+                    .collect::<Vec<_>>(),
+                vec![(name.clone(), ServiceState::Running)]
+            );
+            Ok(())
+        })
+    }
+
     #[test]
     fn test_refuses_to_start_a_service_with_a_name_that_is_taken() -> anyhow::Result<()> {
         let name: Name = "double".parse()?;
@@ -162,13 +1247,13 @@ mod tests {
         supervisor.start(&Start {
             name: Some(name.clone()),
             service: test_services::file_watch(&output_file, vec!["echo".into(), "output".into()]),
-            wait: WaitFor::AMoment,
+            ..Default::default()
         })?;
 
         let result = supervisor.start(&Start {
             name: Some(name.clone()),
             service: test_services::file_watch(&output_file, vec!["echo".into(), "output".into()]),
-            wait: WaitFor::AMoment,
+            ..Default::default()
         });
 
         assert_eq!(result, Err(DaemonError::ServiceAlreadyExistsError { name }));
@@ -180,9 +1265,9 @@ mod tests {
         let service_port = Port::next_available()?;
         let supervisor = Supervisor::new();
         let service_name = supervisor.start(&Start {
-            name: None,
             service: test_services::http_hello_world(service_port),
             wait: WaitFor::Port { port: service_port },
+            ..Default::default()
         })?;
 
         let response_status =
@@ -198,6 +1283,45 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_stops_processes_started_by_a_service() -> anyhow::Result<()> {
+        let temporary_directory = tempfile::tempdir()?;
+        let process_id_file = temporary_directory.path().join("grandchild.pid");
+        let supervisor = Supervisor::new();
+        let service_name = supervisor.start(&Start {
+            service: Service::Program(Program {
+                command: "bash".into(),
+                arguments: vec![
+                    "-c".into(),
+                    "sleep 60 & echo $! > \"$PID_FILE\"; wait".into(),
+                ],
+                environment: Environment::from([(
+                    "PID_FILE".into(),
+                    process_id_file.clone().into(),
+                )]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })?;
+        let grandchild_process_id = eventually(|| {
+            let contents = fs::read_to_string(&process_id_file)?;
+            Ok(contents.trim().parse::<u32>()?)
+        })?;
+        assert!(
+            crate::processes::start_time(grandchild_process_id).is_some(),
+            "The grandchild did not start correctly."
+        );
+
+        supervisor.stop(&Stop { name: service_name })?;
+
+        assert_eq!(
+            crate::processes::start_time(grandchild_process_id),
+            None,
+            "The grandchild did not stop."
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_refuses_to_stop_a_service_with_an_unknown_name() -> anyhow::Result<()> {
         let name: Name = "something".parse()?;
@@ -216,9 +1340,9 @@ mod tests {
         {
             let supervisor = Supervisor::new();
             supervisor.start(&Start {
-                name: None,
                 service: test_services::http_hello_world(service_port),
                 wait: WaitFor::Port { port: service_port },
+                ..Default::default()
             })?;
 
             assert!(
@@ -234,6 +1358,887 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_stops_all_services_and_refuses_to_start_more() -> anyhow::Result<()> {
+        let service_port = Port::next_available()?;
+        let supervisor = Supervisor::new();
+        supervisor.start(&Start {
+            service: test_services::http_hello_world(service_port),
+            wait: WaitFor::Port { port: service_port },
+            ..Default::default()
+        })?;
+
+        let starting_name: Name = "starting".parse()?;
+        thread::scope(|scope| {
+            let starting = scope.spawn(|| {
+                supervisor.start(&Start {
+                    name: Some(starting_name.clone()),
+                    service: Service::Program(test_programs::waits_for_termination()),
+                    wait: WaitFor::Time {
+                        duration: Duration::of(500, DurationUnit::Milliseconds),
+                    },
+                    ..Default::default()
+                })
+            });
+            Duration::QUANTUM.sleep();
+            supervisor.stop_all()?;
+
+            assert!(
+                service_port.is_available(),
+                "The service did not stop correctly."
+            );
+            assert_eq!(
+                starting.join().unwrap(),
+                Err(DaemonError::NoSuchServiceError {
+                    name: starting_name.clone()
+                })
+            );
+            assert_eq!(supervisor.list()?, vec![]);
+            assert_eq!(
+                supervisor.start(&Start {
+                    service: Service::Program(test_programs::waits_for_termination()),
+                    ..Default::default()
+                }),
+                Err(DaemonError::ShuttingDownError)
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_stops_a_group_of_services_in_reverse_order() -> anyhow::Result<()> {
+        let supervisor = Supervisor::new();
+        let group: Name = "suite".parse()?;
+        for (name, group) in [
+            ("first", Some(group.clone())),
+            ("second", Some(group.clone())),
+            ("other", None),
+            ("third", Some(group.clone())),
+        ] {
+            supervisor.start(&Start {
+                name: Some(name.parse()?),
+                service: Service::Program(test_programs::waits_for_termination()),
+                group,
+                ..Default::default()
+            })?;
+        }
+
+        let exit_statuses = supervisor.stop_group(&StopGroup { group })?;
+
+        assert_eq!(
+            exit_statuses,
+            vec![
+                ("third".parse()?, ExitStatus::ExitedWithCode(0)),
+                ("second".parse()?, ExitStatus::ExitedWithCode(0)),
+                ("first".parse()?, ExitStatus::ExitedWithCode(0)),
+            ]
+        );
+        assert_eq!(
+            supervisor
+                .list()?
+                .into_iter()
+                .map(|details| details.name.to_string())
+                .collect::<Vec<_>>(),
+            vec!["other".to_owned()]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_lists_and_stops_services_by_label() -> anyhow::Result<()> {
+        let supervisor = Supervisor::new();
+        for (name, labels) in [
+            ("fast", [("project", "foo"), ("suite", "fast")]),
+            ("slow", [("project", "foo"), ("suite", "slow")]),
+            ("other", [("project", "bar"), ("suite", "fast")]),
+        ] {
+            supervisor.start(&Start {
+                name: Some(name.parse()?),
+                service: Service::Program(test_programs::waits_for_termination()),
+                labels: labels
+                    .into_iter()
+                    .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                    .collect(),
+                ..Default::default()
+            })?;
+        }
+        let names = |details: Vec<ServiceDetails>| {
+            details
+                .into_iter()
+                .map(|details| details.name.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let listed = supervisor.list_selected(&List {
+            selector: "project=foo".parse()?,
+        })?;
+
+        assert_eq!(names(listed), vec!["fast".to_owned(), "slow".to_owned()]);
+
+        let exit_statuses = supervisor.stop_selected(&StopSelected {
+            selector: "project=foo,suite!=slow".parse()?,
+        })?;
+
+        assert_eq!(
+            exit_statuses,
+            vec![("fast".parse()?, ExitStatus::ExitedWithCode(0))]
+        );
+        assert_eq!(
+            names(supervisor.list()?),
+            vec!["slow".to_owned(), "other".to_owned()]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_stops_services_when_their_owner_exits() -> anyhow::Result<()> {
+        let service_port = Port::next_available()?;
+        let supervisor = Supervisor::new();
+        let mut owner = std::process::Command::new("sleep").arg("60").spawn()?;
+        supervisor.start(&Start {
+            service: test_services::http_hello_world(service_port),
+            wait: WaitFor::Port { port: service_port },
+            owner: Some(owner.id()),
+            ..Default::default()
+        })?;
+
+        supervisor.reap()?;
+        assert_eq!(supervisor.list()?.len(), 1);
+
+        owner.kill()?;
+        owner.wait()?;
+
+        eventually(|| {
+            supervisor.reap()?;
+            test_eq(supervisor.list()?, vec![])?;
+            test_eq(service_port.is_available(), true)
+        })?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_keeps_supervising_while_stopping_services_whose_owner_exited() -> anyhow::Result<()> {
+        let supervisor = Supervisor::new();
+        let mut owner = std::process::Command::new("sleep").arg("60").spawn()?;
+        supervisor.start(&Start {
+            name: Some("stubborn".parse()?),
+            service: Service::Program(test_programs::ignores_termination()),
+            owner: Some(owner.id()),
+            ..Default::default()
+        })?;
+
+        owner.kill()?;
+        owner.wait()?;
+
+        let reap_time = Instant::now();
+        supervisor.reap()?;
+        assert!(
+            reap_time.elapsed() < Duration::STOP_TIMEOUT.into(),
+            "Reaping waited for the service to stop."
+        );
+        assert_eq!(supervisor.list()?, vec![]);
+
+        supervisor.stop_all()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_refuses_to_start_a_service_whose_owner_has_exited() -> anyhow::Result<()> {
+        let supervisor = Supervisor::new();
+        let mut owner = std::process::Command::new("true").spawn()?;
+        owner.wait()?;
+
+        let result = supervisor.start(&Start {
+            service: Service::Program(test_programs::waits_for_termination()),
+            owner: Some(owner.id()),
+            ..Default::default()
+        });
+
+        assert_eq!(result, Err(DaemonError::OwnerNotRunningError));
+        assert_eq!(supervisor.list()?, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lists_running_services() -> anyhow::Result<()> {
+        let output_directory = tempfile::tempdir()?;
+        let output_file = output_directory.path().join("output.txt");
+        let service_port = Port::next_available()?;
+        let supervisor = Supervisor::new();
+        let file_watch_service =
+            test_services::file_watch(&output_file, vec!["echo".into(), "output".into()]);
+        let http_service = test_services::http_hello_world(service_port);
+        supervisor.start(&Start {
+            name: Some("first".parse()?),
+            service: file_watch_service.clone(),
+            ..Default::default()
+        })?;
+        supervisor.start(&Start {
+            name: Some("second".parse()?),
+            service: http_service.clone(),
+            wait: WaitFor::Port { port: service_port },
+            ..Default::default()
+        })?;
+
+        let details = supervisor.list()?;
+
+        assert_eq!(
+            details
+                .iter()
+                .map(|d| (
+                    d.name.to_string(),
+                    d.service.clone(),
+                    d.wait.clone(),
+                    d.state
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "first".to_owned(),
+                    file_watch_service,
+                    WaitFor::AMoment,
+                    ServiceState::Running
+                ),
+                (
+                    "second".to_owned(),
+                    http_service,
+                    WaitFor::Port { port: service_port },
+                    ServiceState::Running
+                ),
+            ]
+        );
+        assert_ne!(details[0].process_id, details[1].process_id);
+        assert!(details[0].start_time <= details[1].start_time);
+        Ok(())
+    }
+
+    #[test]
+    fn test_does_not_list_stopped_services() -> anyhow::Result<()> {
+        let service_port = Port::next_available()?;
+        let supervisor = Supervisor::new();
+        let service_name = supervisor.start(&Start {
+            service: test_services::http_hello_world(service_port),
+            wait: WaitFor::Port { port: service_port },
+            ..Default::default()
+        })?;
+
+        supervisor.stop(&Stop { name: service_name })?;
+        let details = supervisor.list()?;
+
+        assert_eq!(details, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_detects_services_that_exit_on_their_own() -> anyhow::Result<()> {
+        let supervisor = Supervisor::new();
+        let service_name = supervisor.start(&Start {
+            service: Service::Program(Program {
+                command: "bash".into(),
+                arguments: vec!["-c".into(), "sleep 0.5; exit 3".into()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        })?;
+
+        let details = eventually(|| {
+            supervisor.reap()?;
+            let details = supervisor.list()?;
+            test_eq(
+                details.iter().map(|d| d.state).collect(),
+                vec![ServiceState::Exited],
+            )?;
+            Ok(details)
+        })?;
+
+        assert_eq!(details[0].name, service_name);
+        assert_eq!(
+            details[0].last_exit.as_ref().map(|exit| &exit.status),
+            Some(&ExitStatus::ExitedWithCode(3))
+        );
+
+        let exit_status = supervisor.stop(&Stop { name: service_name })?;
+
+        assert_eq!(exit_status, ExitStatus::ExitedWithCode(3));
+        assert_eq!(supervisor.list()?, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_restarts_services_that_fail() -> anyhow::Result<()> {
+        let output_directory = tempfile::tempdir()?;
+        let output_file = output_directory.path().join("output.txt");
+        let supervisor = Supervisor::new();
+        supervisor.start(&Start {
+            service: Service::Program(Program {
+                command: "bash".into(),
+                arguments: vec![
+                    "-c".into(),
+                    "echo started >> \"$OUTPUT_FILE\"; sleep 0.3; exit 2".into(),
+                ],
+                environment: [("OUTPUT_FILE".into(), output_file.clone().into())].into(),
+                ..Default::default()
+            }),
+            restart: RestartPolicy::OnFailure {
+                max_retries: Some(2),
+                backoff: Duration::of(100, DurationUnit::Milliseconds),
+            },
+            ..Default::default()
+        })?;
+
+        let details = eventually(|| {
+            supervisor.reap()?;
+            let details = supervisor.list()?;
+            test_eq(
+                details.iter().map(|d| (d.state, d.restarts)).collect(),
+                vec![(ServiceState::Exited, 2)],
+            )?;
+            Ok(details)
+        })?;
+
+        assert_eq!(
+            details[0].last_exit.as_ref().map(|exit| &exit.status),
+            Some(&ExitStatus::ExitedWithCode(2))
+        );
+        assert_eq!(
+            fs::read_to_string(&output_file)?,
+            "started\nstarted\nstarted\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_stops_a_restarted_service_that_does_not_become_ready() -> anyhow::Result<()> {
+        let temporary_directory = tempfile::tempdir()?;
+        let marker = temporary_directory.path().join("started");
+        let port = Port::next_available()?;
+        // the service is only ready while this is listening
+        let listener = std::net::TcpListener::bind(("localhost", port.0))?;
+        let supervisor = Supervisor::new();
+        supervisor.start(&Start {
+            service: Service::Program(Program {
+                command: "bash".into(),
+                arguments: vec![
+                    "-c".into(),
+                    "if [ -e \"$MARKER\" ]; then sleep 60; fi; touch \"$MARKER\"; sleep 0.3; exit 1"
+                        .into(),
+                ],
+                environment: [("MARKER".into(), marker.into())].into(),
+                ..Default::default()
+            }),
+            wait: WaitFor::Port { port },
+            timeout: Some(Duration::of(500, DurationUnit::Milliseconds)),
+            restart: RestartPolicy::OnFailure {
+                max_retries: Some(1),
+                backoff: Duration::QUANTUM,
+            },
+            ..Default::default()
+        })?;
+        drop(listener);
+
+        let details = eventually(|| {
+            supervisor.reap()?;
+            let details = supervisor.list()?;
+            test_eq(
+                details.iter().map(|d| (d.state, d.restarts)).collect(),
+                vec![(ServiceState::Exited, 1)],
+            )?;
+            Ok(details)
+        })?;
+
+        assert_eq!(
+            details[0].last_exit.as_ref().map(|exit| &exit.status),
+            Some(&ExitStatus::ExitedWithSignal(15))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_forgets_restarts_once_a_service_has_been_stable() -> anyhow::Result<()> {
+        let output_directory = tempfile::tempdir()?;
+        let output_file = output_directory.path().join("output.txt");
+        let supervisor = Supervisor::with_options(SupervisorOptions {
+            stable_period: Duration::of(200, DurationUnit::Milliseconds),
+            ..Default::default()
+        });
+        let name = supervisor.start(&Start {
+            service: Service::Program(Program {
+                command: "bash".into(),
+                arguments: vec![
+                    "-c".into(),
+                    "echo started >> \"$OUTPUT_FILE\"; sleep 0.3; exit 2".into(),
+                ],
+                environment: [("OUTPUT_FILE".into(), output_file.clone().into())].into(),
+                ..Default::default()
+            }),
+            restart: RestartPolicy::OnFailure {
+                max_retries: Some(1),
+                backoff: Duration::QUANTUM,
+            },
+            ..Default::default()
+        })?;
+
+        // without forgetting, it would only be restarted once
+        eventually(|| {
+            supervisor.reap()?;
+            let output = fs::read_to_string(&output_file)?;
+            test_eq(output.lines().count() >= 3, true)
+        })?;
+        supervisor.stop(&Stop { name })?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_restarts_in_a_nix_environment_without_holding_up_the_supervisor() -> anyhow::Result<()>
+    {
+        let temporary_directory = tempfile::tempdir()?;
+        let working_directory = temporary_directory.path().to_owned();
+        // the first call succeeds, and any later ones take far too long
+        let (bin, path) = test_programs::stub_nix(
+            &working_directory,
+            "if [ -e \"$0.called\" ]; then touch \"$0.slow\"; sleep 60; fi\ntouch \"$0.called\"\necho '{\"variables\": {}}'\n",
+        )?;
+        fs::write(working_directory.join("shell.nix"), "{ }")?;
+        let supervisor = Supervisor::new();
+        supervisor.start(&Start {
+            service: Service::Nix(NixProgram {
+                environment: NixEnvironment::File("shell.nix".into()),
+                program: Program {
+                    command: "bash".into(),
+                    arguments: vec!["-c".into(), "sleep 0.5; exit 1".into()],
+                    base_environment: Environment::from([("PATH".into(), path.into())]),
+                    working_directory: Some(working_directory.clone()),
+                    ..Default::default()
+                },
+            }),
+            timeout: Some(Duration::of(1, DurationUnit::Seconds)),
+            restart: RestartPolicy::OnFailure {
+                max_retries: None,
+                backoff: Duration::QUANTUM,
+            },
+            ..Default::default()
+        })?;
+        // changing the environment means it has to be realised again
+        fs::write(working_directory.join("shell.nix"), "{ changed = true; }")?;
+
+        eventually(|| {
+            supervisor.reap()?;
+            test_eq(bin.join("nix.slow").exists(), true)
+        })?;
+        let start_time = Instant::now();
+        supervisor.reap()?;
+        supervisor.list()?;
+        let elapsed = Instant::now() - start_time;
+
+        assert!(
+            elapsed < std::time::Duration::from_millis(500),
+            "Expected the elapsed time of {:?} to be a very short amount of time.",
+            elapsed
+        );
+        // once the restart times out, we give up
+        eventually(|| {
+            supervisor.reap()?;
+            let details = supervisor.list()?;
+            test_eq(
+                details.iter().map(|d| (d.state, d.restarts)).collect(),
+                vec![(ServiceState::Exited, 0)],
+            )
+        })
+    }
+
+    #[test]
+    fn test_does_not_restart_services_that_succeed_unless_asked() -> anyhow::Result<()> {
+        let supervisor = Supervisor::new();
+        let program = Program {
+            command: "bash".into(),
+            arguments: vec!["-c".into(), "sleep 0.3".into()],
+            ..Default::default()
+        };
+        let backoff = Duration::of(100, DurationUnit::Milliseconds);
+        supervisor.start(&Start {
+            name: Some("on-failure".parse()?),
+            service: Service::Program(program.clone()),
+            restart: RestartPolicy::OnFailure {
+                max_retries: Some(1),
+                backoff,
+            },
+            ..Default::default()
+        })?;
+        supervisor.start(&Start {
+            name: Some("always".parse()?),
+            service: Service::Program(program),
+            restart: RestartPolicy::Always {
+                max_retries: Some(1),
+                backoff,
+            },
+            ..Default::default()
+        })?;
+
+        eventually(|| {
+            supervisor.reap()?;
+            let details = supervisor.list()?;
+            test_eq(
+                details
+                    .iter()
+                    .map(|d| (d.name.to_string(), d.state, d.restarts))
+                    .collect(),
+                vec![
+                    ("on-failure".to_owned(), ServiceState::Exited, 0),
+                    ("always".to_owned(), ServiceState::Exited, 1),
+                ],
+            )
+        })?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_captures_output() -> anyhow::Result<()> {
+        let state_directory = tempfile::tempdir()?;
+        let supervisor = Supervisor::with_options(SupervisorOptions {
+            state_directory: Some(state_directory.path().to_owned()),
+            ..Default::default()
+        });
+        let name = supervisor.start(&Start {
+            service: Service::Program(Program {
+                command: "bash".into(),
+                arguments: vec![
+                    "-c".into(),
+                    "echo one; echo two; echo three; echo oh no >&2; sleep 10".into(),
+                ],
+                ..Default::default()
+            }),
+            ..Default::default()
+        })?;
+
+        eventually(|| {
+            let stdout = read_logs(
+                &supervisor,
+                Logs {
+                    name: name.clone(),
+                    stream: Stream::Stdout,
+                    tail: None,
+                },
+            )?;
+            test_eq(stdout.as_slice(), b"one\ntwo\nthree\n")
+        })?;
+        eventually(|| {
+            let stderr = read_logs(
+                &supervisor,
+                Logs {
+                    name: name.clone(),
+                    stream: Stream::Stderr,
+                    tail: None,
+                },
+            )?;
+            test_eq(stderr.as_slice(), b"oh no\n")
+        })?;
+        let tail = read_logs(
+            &supervisor,
+            Logs {
+                name: name.clone(),
+                stream: Stream::Stdout,
+                tail: Some(2),
+            },
+        )?;
+
+        assert_eq!(tail.as_slice(), b"two\nthree\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_keeps_output_after_the_service_stops() -> anyhow::Result<()> {
+        let state_directory = tempfile::tempdir()?;
+        let supervisor = Supervisor::with_options(SupervisorOptions {
+            state_directory: Some(state_directory.path().to_owned()),
+            ..Default::default()
+        });
+        let name = supervisor.start(&Start {
+            service: Service::Program(Program {
+                command: "bash".into(),
+                arguments: vec!["-c".into(), "echo goodbye; sleep 10".into()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        })?;
+
+        supervisor.stop(&Stop { name: name.clone() })?;
+        let stdout = read_logs(
+            &supervisor,
+            Logs {
+                name,
+                stream: Stream::Stdout,
+                tail: None,
+            },
+        )?;
+
+        assert_eq!(stdout.as_slice(), b"goodbye\n");
+        Ok(())
+    }
+
+    #[test]
+    #[ntest::timeout(5000)]
+    fn test_follows_output_until_the_service_stops() -> anyhow::Result<()> {
+        let state_directory = tempfile::tempdir()?;
+        let supervisor = Supervisor::with_options(SupervisorOptions {
+            state_directory: Some(state_directory.path().to_owned()),
+            ..Default::default()
+        });
+        let name = supervisor.start(&Start {
+            service: Service::Program(Program {
+                command: "bash".into(),
+                arguments: vec![
+                    "-c".into(),
+                    "echo one; sleep 0.5; echo two; sleep 0.5; echo three".into(),
+                ],
+                ..Default::default()
+            }),
+            ..Default::default()
+        })?;
+
+        let mut chunks: Vec<Vec<u8>> = Vec::new();
+        supervisor.follow_logs(
+            &Logs {
+                name,
+                stream: Stream::Stdout,
+                tail: None,
+            },
+            |contents| {
+                if !contents.is_empty() {
+                    chunks.push(contents.to_vec());
+                }
+                Ok(())
+            },
+        )?;
+
+        assert!(
+            chunks.len() > 1,
+            "Expected the output to arrive in several chunks, but got {:?}.",
+            chunks
+        );
+        assert_eq!(chunks.concat(), b"one\ntwo\nthree\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_waits_for_output() -> anyhow::Result<()> {
+        let state_directory = tempfile::tempdir()?;
+        let output_file = state_directory.path().join("output.txt");
+        let supervisor = Supervisor::with_options(SupervisorOptions {
+            state_directory: Some(state_directory.path().to_owned()),
+            ..Default::default()
+        });
+        supervisor.start(&Start {
+            service: Service::Program(Program {
+                command: "bash".into(),
+                arguments: vec![
+                    "-c".into(),
+                    "sleep 0.5; echo ready > \"$OUTPUT_FILE\"; echo 'Ready!' >&2; sleep 10".into(),
+                ],
+                environment: [("OUTPUT_FILE".into(), output_file.clone().into())].into(),
+                ..Default::default()
+            }),
+            wait: WaitFor::Output {
+                stream: Stream::Stderr,
+                pattern: "^Ready!$".to_owned(),
+            },
+            ..Default::default()
+        })?;
+
+        assert_eq!(fs::read_to_string(&output_file)?, "ready\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_waits_for_new_output_after_a_restart() -> anyhow::Result<()> {
+        let state_directory = tempfile::tempdir()?;
+        let marker = state_directory.path().join("started");
+        let supervisor = Supervisor::with_options(SupervisorOptions {
+            state_directory: Some(state_directory.path().to_owned()),
+            ..Default::default()
+        });
+        // it's only ready the first time
+        supervisor.start(&Start {
+            service: Service::Program(Program {
+                command: "bash".into(),
+                arguments: vec![
+                    "-c".into(),
+                    "if [ -e \"$MARKER\" ]; then sleep 60; fi; touch \"$MARKER\"; echo ready; sleep 0.3; exit 1"
+                        .into(),
+                ],
+                environment: [("MARKER".into(), marker.into())].into(),
+                ..Default::default()
+            }),
+            wait: WaitFor::Output {
+                stream: Stream::Stdout,
+                pattern: "^ready$".to_owned(),
+            },
+            timeout: Some(Duration::of(500, DurationUnit::Milliseconds)),
+            restart: RestartPolicy::OnFailure {
+                max_retries: Some(1),
+                backoff: Duration::QUANTUM,
+            },
+            ..Default::default()
+        })?;
+
+        eventually(|| {
+            supervisor.reap()?;
+            let details = supervisor.list()?;
+            test_eq(
+                details.iter().map(|d| (d.state, d.restarts)).collect(),
+                vec![(ServiceState::Exited, 1)],
+            )
+        })
+    }
+
+    #[test]
+    fn test_stops_waiting_as_soon_as_a_service_exits() -> anyhow::Result<()> {
+        let supervisor = Supervisor::new();
+        let start_time = Instant::now();
+        let result = supervisor.start(&Start {
+            service: Service::Program(Program {
+                command: "bash".into(),
+                arguments: vec!["-c".into(), "exit 3".into()],
+                ..Default::default()
+            }),
+            wait: WaitFor::Port {
+                port: Port::next_available()?,
+            },
+            timeout: Some(Duration::of(10, DurationUnit::Seconds)),
+            ..Default::default()
+        });
+        let elapsed = Instant::now() - start_time;
+
+        assert_eq!(result, Err(DaemonError::ServiceCrashedError));
+        assert!(
+            elapsed < std::time::Duration::from_secs(2),
+            "Expected the elapsed time of {:?} to be a very short amount of time.",
+            elapsed
+        );
+        let details = supervisor.list()?;
+        assert_eq!(
+            details[0].last_exit.as_ref().map(|exit| &exit.status),
+            Some(&ExitStatus::ExitedWithCode(3))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_does_not_start_a_service_with_an_invalid_pattern() -> anyhow::Result<()> {
+        let state_directory = tempfile::tempdir()?;
+        let output_file = state_directory.path().join("output.txt");
+        let supervisor = Supervisor::with_options(SupervisorOptions {
+            state_directory: Some(state_directory.path().to_owned()),
+            ..Default::default()
+        });
+        let result = supervisor.start(&Start {
+            service: Service::Program(Program {
+                command: "bash".into(),
+                arguments: vec!["-c".into(), "echo started > \"$OUTPUT_FILE\"".into()],
+                environment: [("OUTPUT_FILE".into(), output_file.clone().into())].into(),
+                ..Default::default()
+            }),
+            wait: WaitFor::Output {
+                stream: Stream::Stdout,
+                pattern: "(".to_owned(),
+            },
+            ..Default::default()
+        });
+
+        assert!(
+            matches!(result, Err(DaemonError::InvalidPatternError(_))),
+            "Expected an invalid pattern error but got {:?}",
+            result
+        );
+        Duration::QUANTUM.sleep();
+        assert!(!output_file.exists(), "The service was started.");
+        assert_eq!(supervisor.list()?, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_restores_services_that_are_still_running() -> anyhow::Result<()> {
+        let state_directory = tempfile::tempdir()?;
+        let options = SupervisorOptions {
+            state_directory: Some(state_directory.path().to_owned()),
+            ..Default::default()
+        };
+        let name: Name = "survivor".parse()?;
+        let crashed_supervisor = Supervisor::with_options(options.clone());
+        crashed_supervisor.start(&Start {
+            name: Some(name.clone()),
+            service: Service::Program(test_programs::waits_for_termination()),
+            ..Default::default()
+        })?;
+        let process_id = crashed_supervisor.list()?[0].process_id;
+        // simulate a crash, by never cleaning up
+        std::mem::forget(crashed_supervisor);
+
+        let supervisor = Supervisor::with_options(options);
+        supervisor.restore()?;
+        let details = supervisor.list()?;
+
+        assert_eq!(details.len(), 1);
+        assert_eq!(details[0].name, name);
+        assert_eq!(details[0].process_id, process_id);
+        assert_eq!(details[0].state, ServiceState::Running);
+
+        supervisor.stop(&Stop { name })?;
+        assert_eq!(crate::processes::start_time(process_id), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_restores_services_that_stopped_as_exited() -> anyhow::Result<()> {
+        let state_directory = tempfile::tempdir()?;
+        let options = SupervisorOptions {
+            state_directory: Some(state_directory.path().to_owned()),
+            ..Default::default()
+        };
+        let crashed_supervisor = Supervisor::with_options(options.clone());
+        crashed_supervisor.start(&Start {
+            service: Service::Program(test_programs::waits_for_termination()),
+            ..Default::default()
+        })?;
+        let process_id = crashed_supervisor.list()?[0].process_id;
+        // simulate a crash, by never cleaning up
+        std::mem::forget(crashed_supervisor);
+        nix::sys::signal::kill(
+            nix::unistd::Pid::from_raw(process_id.try_into()?),
+            nix::sys::signal::Signal::SIGKILL,
+        )?;
+        eventually(|| test_eq(crate::processes::start_time(process_id), None))?;
+
+        let supervisor = Supervisor::with_options(options);
+        supervisor.restore()?;
+        let details = supervisor.list()?;
+
+        assert_eq!(details.len(), 1);
+        assert_eq!(details[0].state, ServiceState::Exited);
+        assert_eq!(
+            details[0].last_exit.as_ref().map(|exit| &exit.status),
+            Some(&ExitStatus::None)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_refuses_to_provide_output_if_it_is_not_captured() -> anyhow::Result<()> {
+        let supervisor = Supervisor::new();
+
+        let result = read_logs(
+            &supervisor,
+            Logs {
+                name: "something".parse()?,
+                stream: Stream::Stdout,
+                tail: None,
+            },
+        );
+
+        assert_eq!(result, Err(DaemonError::OutputNotCapturedError));
+        Ok(())
+    }
+
     #[test]
     fn test_responds_with_the_name_if_one_is_provided() -> anyhow::Result<()> {
         let output_directory = tempfile::tempdir()?;
@@ -243,7 +2248,7 @@ mod tests {
         let name = supervisor.start(&Start {
             name: Some("thingamabob".parse()?),
             service: test_services::file_watch(&output_file, vec!["echo".into(), "output".into()]),
-            wait: WaitFor::AMoment,
+            ..Default::default()
         })?;
 
         assert_eq!(name, "thingamabob".parse()?);
@@ -258,20 +2263,18 @@ mod tests {
 
         let supervisor = Supervisor::new();
         let name_1 = supervisor.start(&Start {
-            name: None,
             service: test_services::file_watch(
                 &output_file_1,
                 vec!["echo".into(), "output".into()],
             ),
-            wait: WaitFor::AMoment,
+            ..Default::default()
         })?;
         let name_2 = supervisor.start(&Start {
-            name: None,
             service: test_services::file_watch(
                 &output_file_2,
                 vec!["echo".into(), "output".into()],
             ),
-            wait: WaitFor::AMoment,
+            ..Default::default()
         })?;
 
         assert_ne!(name_1, name_2);
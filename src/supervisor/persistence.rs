@@ -0,0 +1,60 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::communication::Exit;
+use crate::labels::Labels;
+use crate::names::Name;
+use crate::restart::RestartPolicy;
+use crate::services::Service;
+use crate::timing::Duration;
+use crate::wait::WaitFor;
+
+use super::Owner;
+
+/// What we remember about a service, so that we can pick up where we left off
+/// if the daemon crashes.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub(super) struct PersistedService {
+    pub name: Name,
+    pub service: Service,
+    pub process_id: u32,
+    pub process_start_time: Option<u64>,
+    pub start_time: chrono::DateTime<chrono::Utc>,
+    pub wait: WaitFor,
+    pub timeout: Duration,
+    pub restart: RestartPolicy,
+    pub running: bool,
+    pub restarts: u32,
+    pub last_exit: Option<Exit>,
+    #[serde(default)]
+    pub owner: Option<Owner>,
+    #[serde(default)]
+    pub group: Option<Name>,
+    #[serde(default)]
+    pub labels: Labels,
+}
+
+pub(super) fn serialize(services: &[PersistedService]) -> io::Result<Vec<u8>> {
+    Ok(serde_json::to_vec_pretty(services)?)
+}
+
+/// Writes the state file, replacing it atomically so that a crash halfway
+/// through doesn't leave us with a corrupt file.
+pub(super) fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    let temporary_path = path.with_extension("tmp");
+    fs::write(&temporary_path, contents)?;
+    fs::rename(temporary_path, path)
+}
+
+/// Reads the state file, if there is one.
+pub(super) fn read(path: &Path) -> io::Result<Vec<PersistedService>> {
+    match fs::read(path) {
+        Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(error) => Err(error),
+    }
+}
@@ -16,7 +16,9 @@ pub fn test_eq<A: std::fmt::Debug + PartialEq>(left: A, right: A) -> anyhow::Res
     }
 }
 
-pub fn eventually<A: std::fmt::Debug>(action: impl Fn() -> anyhow::Result<A>) -> anyhow::Result<A> {
+pub fn eventually<A: std::fmt::Debug>(
+    mut action: impl FnMut() -> anyhow::Result<A>,
+) -> anyhow::Result<A> {
     let start_time = time::Instant::now();
     loop {
         let result = action();
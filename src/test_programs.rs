@@ -1,6 +1,8 @@
 #![cfg(test)]
 
-use std::path::PathBuf;
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
 
 use crate::programs::Program;
 
@@ -9,7 +11,7 @@ pub fn waits_for_termination() -> Program {
     Program {
         command: "bash".into(),
         arguments: vec![script.into()],
-        environment: Default::default(),
+        ..Default::default()
     }
 }
 
@@ -18,10 +20,27 @@ pub fn ignores_termination() -> Program {
     Program {
         command: "bash".into(),
         arguments: vec![script.into()],
-        environment: Default::default(),
+        ..Default::default()
     }
 }
 
+/// Writes a fake `nix`, which runs the given shell script, into a new `bin`
+/// directory, and returns a `PATH` with that directory in front.
+pub fn stub_nix(directory: &Path, script: &str) -> io::Result<(PathBuf, OsString)> {
+    let bin = directory.join("bin");
+    std::fs::create_dir(&bin)?;
+    std::fs::write(bin.join("nix"), format!("#!/bin/sh\n{}", script))?;
+    std::fs::set_permissions(
+        bin.join("nix"),
+        std::os::unix::fs::PermissionsExt::from_mode(0o755),
+    )?;
+    let path = std::env::join_paths(std::iter::once(bin.clone()).chain(std::env::split_paths(
+        &std::env::var_os("PATH").unwrap_or_default(),
+    )))
+    .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+    Ok((bin, path))
+}
+
 fn root() -> PathBuf {
     std::env::var("CARGO_MANIFEST_DIR")
         .expect("Missing CARGO_MANIFEST_DIR")
@@ -13,7 +13,7 @@ pub fn file_watch(output_path: &Path, mut command: Vec<Argument>) -> Service {
     Service::Program(Program {
         command: program.into(),
         arguments,
-        environment: Default::default(),
+        ..Default::default()
     })
 }
 
@@ -23,6 +23,7 @@ pub fn http_hello_world(port: Port) -> Service {
         command: "node".into(),
         arguments: vec![script.into()],
         environment: [("PORT".into(), format!("{}", port).into())].into(),
+        ..Default::default()
     })
 }
 
@@ -27,7 +27,14 @@ impl Duration {
     pub const FOREVER: Self = Self(std::time::Duration::MAX);
 
     pub const QUANTUM: Self = Self::of(100, DurationUnit::Milliseconds);
+    pub const DAEMON_START_TIMEOUT: Self = Self::of(10, DurationUnit::Seconds);
+    pub const START_TIMEOUT: Self = Self::of(60, DurationUnit::Seconds);
     pub const STOP_TIMEOUT: Self = Self::of(10, DurationUnit::Seconds);
+    pub const HEARTBEAT: Self = Self::of(1, DurationUnit::Seconds);
+    pub const HTTP_REQUEST_TIMEOUT: Self = Self::of(5, DurationUnit::Seconds);
+    pub const RESTART_BACKOFF: Self = Self::of(1, DurationUnit::Seconds);
+    pub const MAX_RESTART_BACKOFF: Self = Self::of(60, DurationUnit::Seconds);
+    pub const STABLE_PERIOD: Self = Self::of(60, DurationUnit::Seconds);
 
     pub const fn of(magnitude: u64, unit: DurationUnit) -> Self {
         Self(match unit {
@@ -39,9 +46,81 @@ impl Duration {
     pub fn sleep(&self) {
         std::thread::sleep((*self).into())
     }
+
+    pub fn saturating_mul(self, factor: u32) -> Self {
+        Self(self.0.saturating_mul(factor))
+    }
+
+    pub fn saturating_sub(self, other: Self) -> Self {
+        Self(self.0.saturating_sub(other.0))
+    }
+}
+
+impl std::str::FromStr for Duration {
+    type Err = DurationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (magnitude, unit) = if let Some(magnitude) = s.strip_suffix("ms") {
+            (magnitude, DurationUnit::Milliseconds)
+        } else if let Some(magnitude) = s.strip_suffix('s') {
+            (magnitude, DurationUnit::Seconds)
+        } else {
+            (s, DurationUnit::Seconds)
+        };
+        magnitude
+            .parse::<u64>()
+            .map(|magnitude| Self::of(magnitude, unit))
+            .map_err(|_| DurationError::InvalidDuration(s.to_owned()))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DurationError {
+    InvalidDuration(String),
+}
+
+impl std::fmt::Display for DurationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            DurationError::InvalidDuration(duration) => write!(f, "invalid duration: {:?}, the duration must be a whole number of seconds (e.g. \"5s\") or milliseconds (e.g. \"500ms\")", duration),
+        }
+    }
 }
 
+impl std::error::Error for DurationError {}
+
 pub enum DurationUnit {
     Milliseconds,
     Seconds,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_seconds() {
+        assert_eq!("5s".parse(), Ok(Duration::of(5, DurationUnit::Seconds)));
+    }
+
+    #[test]
+    fn test_parses_milliseconds() {
+        assert_eq!(
+            "250ms".parse(),
+            Ok(Duration::of(250, DurationUnit::Milliseconds))
+        );
+    }
+
+    #[test]
+    fn test_parses_seconds_without_a_unit() {
+        assert_eq!("12".parse(), Ok(Duration::of(12, DurationUnit::Seconds)));
+    }
+
+    #[test]
+    fn test_rejects_invalid_durations() {
+        assert_eq!(
+            "five seconds".parse::<Duration>(),
+            Err(DurationError::InvalidDuration("five seconds".to_owned()))
+        );
+    }
+}
@@ -1,43 +1,343 @@
+use std::collections::VecDeque;
 use std::time::Instant;
 
-use crate::error::{DaemonError, DaemonResult};
+use crate::error::{DaemonError, DaemonResult, InvalidPattern, InvalidUrl};
+use crate::output::{Follower, Output, Stream};
 use crate::ports::Port;
 use crate::timing::Duration;
 
-#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum WaitFor {
+    #[default]
     AMoment,
-    Time { duration: Duration },
-    Port { port: Port },
+    Time {
+        duration: Duration,
+    },
+    Port {
+        port: Port,
+    },
+    Http {
+        url: String,
+        expected_status: Option<u16>,
+        body_contains: Option<String>,
+    },
+    Output {
+        stream: Stream,
+        pattern: String,
+    },
+    All {
+        conditions: Vec<WaitFor>,
+    },
+    Any {
+        conditions: Vec<WaitFor>,
+    },
+    Sequence {
+        conditions: Vec<WaitFor>,
+    },
 }
 
 impl WaitFor {
-    pub(crate) fn block_until_ready(&self, timeout: Duration) -> DaemonResult<()> {
+    /// Gets ready to wait for the condition, before the service starts.
+    ///
+    /// This checks the condition is valid, so that we don't start a service
+    /// we can't wait for, and only considers output written from now on, so
+    /// that output from a previous run doesn't count.
+    pub(crate) fn prepare(&self, output: &Output) -> DaemonResult<Waiter> {
+        Waiter::new(self, output)
+    }
+}
+
+/// Tracks progress towards a condition, so that it can be checked repeatedly.
+pub(crate) enum Waiter {
+    AMoment,
+    Time {
+        duration: Duration,
+        start_time: Option<Instant>,
+    },
+    Port {
+        port: Port,
+    },
+    Http {
+        url: String,
+        parsed_url: reqwest::Url,
+        expected_status: Option<u16>,
+        body_contains: Option<String>,
+        client: reqwest::blocking::Client,
+    },
+    Output {
+        stream: Stream,
+        pattern: String,
+        regex: regex::bytes::Regex,
+        follower: Follower,
+        unmatched: Vec<u8>,
+    },
+    All(Vec<Waiter>),
+    Any(Vec<Waiter>),
+    Sequence(VecDeque<Waiter>),
+}
+
+impl Waiter {
+    /// Blocks until the condition is met.
+    ///
+    /// If `exited` reports that the service has exited, we stop waiting, as it
+    /// will never be ready.
+    ///
+    /// Composite conditions share the same timeout; if it elapses, the error
+    /// describes the parts of the condition that were not met.
+    pub(crate) fn block_until_ready(
+        mut self,
+        timeout: Duration,
+        mut exited: impl FnMut() -> DaemonResult<bool>,
+    ) -> DaemonResult<()> {
+        // if the deadline is too far away to represent, we wait forever
+        let deadline = Instant::now().checked_add(timeout.into());
+        loop {
+            if self.poll(deadline)? {
+                return Ok(());
+            }
+            if exited()? {
+                return Err(DaemonError::ServiceCrashedError);
+            }
+            if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+                return Err(DaemonError::TimeOut(self.pending()));
+            }
+            Duration::QUANTUM.sleep();
+        }
+    }
+
+    fn new(condition: &WaitFor, output: &Output) -> DaemonResult<Self> {
+        Ok(match condition {
+            WaitFor::AMoment => Self::AMoment,
+            WaitFor::Time { duration } => Self::Time {
+                duration: *duration,
+                start_time: None,
+            },
+            WaitFor::Port { port } => Self::Port { port: *port },
+            WaitFor::Http {
+                url,
+                expected_status,
+                body_contains,
+            } => {
+                let invalid = |message: String| {
+                    DaemonError::InvalidUrlError(InvalidUrl {
+                        url: url.clone(),
+                        message,
+                    })
+                };
+                let parsed_url =
+                    reqwest::Url::parse(url).map_err(|error| invalid(error.to_string()))?;
+                // we're built without TLS, so HTTPS would never succeed
+                if parsed_url.scheme() != "http" {
+                    return Err(invalid("only http:// URLs are supported".to_owned()));
+                }
+                Self::Http {
+                    url: url.clone(),
+                    parsed_url,
+                    expected_status: *expected_status,
+                    body_contains: body_contains.clone(),
+                    client: reqwest::blocking::Client::new(),
+                }
+            }
+            WaitFor::Output { stream, pattern } => {
+                let regex = regex::bytes::Regex::new(pattern).map_err(|error| {
+                    DaemonError::InvalidPatternError(InvalidPattern {
+                        pattern: pattern.clone(),
+                        message: error.to_string(),
+                    })
+                })?;
+                let path = output
+                    .path(*stream)
+                    .ok_or(DaemonError::OutputNotCapturedError)?;
+                // the service may not have created the file yet
+                output
+                    .open(*stream)
+                    .map_err(|error| DaemonError::CaptureOutputError(error.into()))?;
+                let follower = Follower::open_at_end(path)
+                    .map_err(|error| DaemonError::ReadOutputError(error.into()))?;
+                Self::Output {
+                    stream: *stream,
+                    pattern: pattern.clone(),
+                    regex,
+                    follower,
+                    unmatched: Vec::new(),
+                }
+            }
+            WaitFor::All { conditions } => Self::All(Self::new_all(conditions, output)?),
+            // any of nothing would never be satisfied
+            WaitFor::Any { conditions } if conditions.is_empty() => {
+                return Err(DaemonError::NothingToWaitForError);
+            }
+            WaitFor::Any { conditions } => Self::Any(Self::new_all(conditions, output)?),
+            WaitFor::Sequence { conditions } => {
+                Self::Sequence(Self::new_all(conditions, output)?.into())
+            }
+        })
+    }
+
+    fn new_all(conditions: &[WaitFor], output: &Output) -> DaemonResult<Vec<Self>> {
+        conditions
+            .iter()
+            .map(|condition| Self::new(condition, output))
+            .collect()
+    }
+
+    /// Checks whether the condition has been met, without blocking for long.
+    fn poll(&mut self, deadline: Option<Instant>) -> DaemonResult<bool> {
         match self {
             Self::AMoment => {
                 Duration::QUANTUM.sleep();
-                Ok(())
+                Ok(true)
             }
-            Self::Time { duration } => {
-                if *duration >= timeout {
-                    return Err(DaemonError::TimeOut);
+            Self::Time {
+                duration,
+                start_time,
+            } => {
+                // the clock starts when we first check, so that sequences work
+                let start_time = start_time.get_or_insert_with(Instant::now);
+                Ok(Instant::now() - *start_time >= (*duration).into())
+            }
+            Self::Port { port } => Ok(port.is_in_use()),
+            Self::Http {
+                url,
+                parsed_url,
+                expected_status,
+                body_contains,
+                client,
+            } => {
+                let request_timeout = match deadline {
+                    None => Duration::HTTP_REQUEST_TIMEOUT.into(),
+                    Some(deadline) => deadline
+                        .saturating_duration_since(Instant::now())
+                        .min(Duration::HTTP_REQUEST_TIMEOUT.into()),
+                };
+                if request_timeout.is_zero() {
+                    return Ok(false);
                 }
-                duration.sleep();
-                Ok(())
+                let response = match client
+                    .get(parsed_url.clone())
+                    .timeout(request_timeout)
+                    .send()
+                {
+                    Ok(response) => response,
+                    // these won't get better by trying again
+                    Err(error) if error.is_builder() || error.is_redirect() => {
+                        return Err(DaemonError::InvalidUrlError(InvalidUrl {
+                            url: url.clone(),
+                            message: error.to_string(),
+                        }));
+                    }
+                    // otherwise, the service probably isn't answering yet
+                    Err(_) => {
+                        return Ok(false);
+                    }
+                };
+                let status_matches = match expected_status {
+                    None => response.status().is_success(),
+                    Some(expected) => response.status().as_u16() == *expected,
+                };
+                if !status_matches {
+                    return Ok(false);
+                }
+                Ok(match body_contains {
+                    None => true,
+                    Some(expected) => response
+                        .text()
+                        .map_or(false, |body| body.contains(expected.as_str())),
+                })
             }
-            Self::Port { port } => {
-                let start_time = Instant::now();
-                while port.is_available() {
-                    Duration::QUANTUM.sleep();
-                    if Instant::now() - start_time > timeout.into() {
-                        return Err(DaemonError::TimeOut);
+            Self::Output {
+                regex,
+                follower,
+                unmatched,
+                ..
+            } => {
+                for chunk in follower.read_more() {
+                    unmatched.append(
+                        &mut chunk.map_err(|error| DaemonError::ReadOutputError(error.into()))?,
+                    );
+                    // we only look at complete lines, and keep the rest for later
+                    if let Some(end) = unmatched.iter().rposition(|byte| *byte == b'\n') {
+                        if unmatched[..end]
+                            .split(|byte| *byte == b'\n')
+                            .any(|line| regex.is_match(line))
+                        {
+                            return Ok(true);
+                        }
+                        unmatched.drain(..=end);
                     }
                 }
-                Ok(())
+                Ok(false)
+            }
+            Self::All(waiters) => {
+                let mut pending = Vec::with_capacity(waiters.len());
+                for mut waiter in waiters.drain(..) {
+                    if !waiter.poll(deadline)? {
+                        pending.push(waiter);
+                    }
+                }
+                *waiters = pending;
+                Ok(waiters.is_empty())
+            }
+            Self::Any(waiters) => {
+                for waiter in waiters.iter_mut() {
+                    if waiter.poll(deadline)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            Self::Sequence(waiters) => {
+                while let Some(waiter) = waiters.front_mut() {
+                    if !waiter.poll(deadline)? {
+                        return Ok(false);
+                    }
+                    waiters.pop_front();
+                }
+                Ok(true)
             }
         }
     }
+
+    /// Describes the parts of the condition that have not yet been met.
+    fn pending(&self) -> WaitFor {
+        match self {
+            Self::AMoment => WaitFor::AMoment,
+            Self::Time { duration, .. } => WaitFor::Time {
+                duration: *duration,
+            },
+            Self::Port { port } => WaitFor::Port { port: *port },
+            Self::Http {
+                url,
+                expected_status,
+                body_contains,
+                ..
+            } => WaitFor::Http {
+                url: url.clone(),
+                expected_status: *expected_status,
+                body_contains: body_contains.clone(),
+            },
+            Self::Output {
+                stream, pattern, ..
+            } => WaitFor::Output {
+                stream: *stream,
+                pattern: pattern.clone(),
+            },
+            Self::All(waiters) => match waiters.as_slice() {
+                [waiter] => waiter.pending(),
+                waiters => WaitFor::All {
+                    conditions: waiters.iter().map(Self::pending).collect(),
+                },
+            },
+            Self::Any(waiters) => WaitFor::Any {
+                conditions: waiters.iter().map(Self::pending).collect(),
+            },
+            Self::Sequence(waiters) => match waiters.front() {
+                Some(waiter) => waiter.pending(),
+                None => WaitFor::Sequence { conditions: vec![] },
+            },
+        }
+    }
 }
 
 impl std::fmt::Display for WaitFor {
@@ -46,24 +346,80 @@ impl std::fmt::Display for WaitFor {
             WaitFor::AMoment => write!(f, "a moment"),
             WaitFor::Time { duration } => write!(f, "{}", duration),
             WaitFor::Port { port } => write!(f, "port {}", port),
+            WaitFor::Http {
+                url,
+                expected_status,
+                body_contains,
+            } => {
+                write!(f, "HTTP {}", url)?;
+                if let Some(expected_status) = expected_status {
+                    write!(f, " to respond with status {}", expected_status)?;
+                }
+                if let Some(body_contains) = body_contains {
+                    write!(f, " containing {:?}", body_contains)?;
+                }
+                Ok(())
+            }
+            WaitFor::Output { stream, pattern } => {
+                write!(f, "a line on {} matching {:?}", stream, pattern)
+            }
+            WaitFor::All { conditions } => {
+                write!(f, "all of (")?;
+                write_separated(f, conditions, ", ")?;
+                write!(f, ")")
+            }
+            WaitFor::Any { conditions } => {
+                write!(f, "any of (")?;
+                write_separated(f, conditions, ", ")?;
+                write!(f, ")")
+            }
+            WaitFor::Sequence { conditions } => {
+                write!(f, "(")?;
+                write_separated(f, conditions, ", then ")?;
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+fn write_separated(
+    f: &mut std::fmt::Formatter<'_>,
+    conditions: &[WaitFor],
+    separator: &str,
+) -> std::fmt::Result {
+    for (index, condition) in conditions.iter().enumerate() {
+        if index > 0 {
+            write!(f, "{}", separator)?;
         }
+        write!(f, "{}", condition)?;
     }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
+    use std::fs;
+    use std::io::Write;
     use std::net;
     use std::thread;
     use std::time::Instant;
 
+    use crate::output::Output;
+    use crate::test_services;
     use crate::timing::DurationUnit;
 
     use super::*;
 
+    /// Blocks until the condition is met, ignoring any existing output.
+    fn block_until_ready(wait: &WaitFor, output: &Output, timeout: Duration) -> DaemonResult<()> {
+        wait.prepare(output)?
+            .block_until_ready(timeout, || Ok(false))
+    }
+
     #[test]
     fn test_wait_a_moment() -> anyhow::Result<()> {
         let start_time = Instant::now();
-        WaitFor::AMoment.block_until_ready(Duration::ZERO)?;
+        block_until_ready(&WaitFor::AMoment, &Output::Inherit, Duration::ZERO)?;
         let end_time = Instant::now();
 
         let elapsed = end_time - start_time;
@@ -82,7 +438,11 @@ mod tests {
         };
 
         let start_time = Instant::now();
-        wait.block_until_ready(Duration::of(2, DurationUnit::Seconds))?;
+        block_until_ready(
+            &wait,
+            &Output::Inherit,
+            Duration::of(2, DurationUnit::Seconds),
+        )?;
         let end_time = Instant::now();
 
         let elapsed = end_time - start_time;
@@ -101,7 +461,11 @@ mod tests {
             duration: Duration::of(1, DurationUnit::Seconds),
         };
 
-        let actual = wait.block_until_ready(Duration::of(100, DurationUnit::Milliseconds));
+        let actual = block_until_ready(
+            &wait,
+            &Output::Inherit,
+            Duration::of(100, DurationUnit::Milliseconds),
+        );
 
         assert!(actual.is_err(), "Expected an error but got {:?}", actual);
         Ok(())
@@ -118,7 +482,11 @@ mod tests {
             listener.accept().unwrap(); // block until we receive a connection
         });
 
-        wait.block_until_ready(Duration::of(1, DurationUnit::Seconds))?;
+        block_until_ready(
+            &wait,
+            &Output::Inherit,
+            Duration::of(1, DurationUnit::Seconds),
+        )?;
 
         Ok(())
     }
@@ -131,9 +499,427 @@ mod tests {
         }
         let wait = WaitFor::Port { port };
 
-        let actual = wait.block_until_ready(Duration::of(100, DurationUnit::Milliseconds));
+        let actual = block_until_ready(
+            &wait,
+            &Output::Inherit,
+            Duration::of(100, DurationUnit::Milliseconds),
+        );
 
         assert!(actual.is_err(), "Expected an error but got {:?}", actual);
         Ok(())
     }
+
+    #[test]
+    fn test_wait_for_http() -> anyhow::Result<()> {
+        let port = Port::next_available()?;
+        let mut service = test_services::http_hello_world(port)
+            .start(&Output::Inherit, Duration::START_TIMEOUT)?;
+        let wait = WaitFor::Http {
+            url: format!("http://localhost:{}/", port),
+            expected_status: Some(200),
+            body_contains: Some("Hello".to_owned()),
+        };
+
+        let result = block_until_ready(
+            &wait,
+            &Output::Inherit,
+            Duration::of(5, DurationUnit::Seconds),
+        );
+
+        service.stop(Duration::STOP_TIMEOUT)?;
+        result?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_time_out_waiting_for_an_unexpected_http_status() -> anyhow::Result<()> {
+        let port = Port::next_available()?;
+        let mut service = test_services::http_hello_world(port)
+            .start(&Output::Inherit, Duration::START_TIMEOUT)?;
+        let wait = WaitFor::Http {
+            url: format!("http://localhost:{}/", port),
+            expected_status: Some(404),
+            body_contains: None,
+        };
+
+        let actual = block_until_ready(
+            &wait,
+            &Output::Inherit,
+            Duration::of(1, DurationUnit::Seconds),
+        );
+
+        service.stop(Duration::STOP_TIMEOUT)?;
+        assert!(actual.is_err(), "Expected an error but got {:?}", actual);
+        Ok(())
+    }
+
+    #[test]
+    fn test_time_out_waiting_for_an_unexpected_http_body() -> anyhow::Result<()> {
+        let port = Port::next_available()?;
+        let mut service = test_services::http_hello_world(port)
+            .start(&Output::Inherit, Duration::START_TIMEOUT)?;
+        let wait = WaitFor::Http {
+            url: format!("http://localhost:{}/", port),
+            expected_status: None,
+            body_contains: Some("Goodbye".to_owned()),
+        };
+
+        let actual = block_until_ready(
+            &wait,
+            &Output::Inherit,
+            Duration::of(1, DurationUnit::Seconds),
+        );
+
+        service.stop(Duration::STOP_TIMEOUT)?;
+        assert!(actual.is_err(), "Expected an error but got {:?}", actual);
+        Ok(())
+    }
+
+    #[test]
+    fn test_time_out_waiting_for_http() -> anyhow::Result<()> {
+        let port = Port::next_available()?;
+        let wait = WaitFor::Http {
+            url: format!("http://localhost:{}/", port),
+            expected_status: None,
+            body_contains: None,
+        };
+
+        let actual = block_until_ready(
+            &wait,
+            &Output::Inherit,
+            Duration::of(100, DurationUnit::Milliseconds),
+        );
+
+        assert!(actual.is_err(), "Expected an error but got {:?}", actual);
+        Ok(())
+    }
+
+    #[test]
+    fn test_refuses_to_wait_for_an_unsupported_url() -> anyhow::Result<()> {
+        for url in ["https://localhost/", "localhost:8080"] {
+            let wait = WaitFor::Http {
+                url: url.to_owned(),
+                expected_status: None,
+                body_contains: None,
+            };
+
+            let actual = block_until_ready(
+                &wait,
+                &Output::Inherit,
+                Duration::of(100, DurationUnit::Milliseconds),
+            );
+
+            assert!(
+                matches!(actual, Err(DaemonError::InvalidUrlError(_))),
+                "Expected an invalid URL error for {:?} but got {:?}",
+                url,
+                actual
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_wait_for_output() -> anyhow::Result<()> {
+        let output_directory = tempfile::tempdir()?;
+        let output = Output::in_directory(output_directory.path(), &"test".parse()?);
+        let output_path = output.path(Stream::Stdout).unwrap().to_owned();
+        fs::write(&output_path, "starting\n")?;
+        let wait = WaitFor::Output {
+            stream: Stream::Stdout,
+            pattern: "^Listening on [0-9]+$".to_owned(),
+        };
+
+        thread::spawn(move || {
+            Duration::QUANTUM.sleep();
+            let mut file = fs::OpenOptions::new()
+                .append(true)
+                .open(output_path)
+                .unwrap();
+            file.write_all(b"Listening on 80").unwrap();
+            Duration::QUANTUM.sleep();
+            file.write_all(b"80\n").unwrap();
+        });
+
+        block_until_ready(&wait, &output, Duration::of(1, DurationUnit::Seconds))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_time_out_waiting_for_output() -> anyhow::Result<()> {
+        let output_directory = tempfile::tempdir()?;
+        let output = Output::in_directory(output_directory.path(), &"test".parse()?);
+        fs::write(output.path(Stream::Stderr).unwrap(), "Listening on 8080\n")?;
+        let wait = WaitFor::Output {
+            stream: Stream::Stdout,
+            pattern: "Listening".to_owned(),
+        };
+        fs::write(output.path(Stream::Stdout).unwrap(), "starting\n")?;
+
+        let actual = block_until_ready(
+            &wait,
+            &output,
+            Duration::of(100, DurationUnit::Milliseconds),
+        );
+
+        assert_eq!(actual, Err(DaemonError::TimeOut(wait.clone())));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ignores_output_written_before_waiting() -> anyhow::Result<()> {
+        let output_directory = tempfile::tempdir()?;
+        let output = Output::in_directory(output_directory.path(), &"test".parse()?);
+        fs::write(output.path(Stream::Stdout).unwrap(), "Listening on 8080\n")?;
+        let wait = WaitFor::Output {
+            stream: Stream::Stdout,
+            pattern: "Listening".to_owned(),
+        };
+
+        let actual = block_until_ready(
+            &wait,
+            &output,
+            Duration::of(100, DurationUnit::Milliseconds),
+        );
+
+        assert_eq!(actual, Err(DaemonError::TimeOut(wait.clone())));
+        Ok(())
+    }
+
+    #[test]
+    fn test_stops_waiting_if_the_service_exits() -> anyhow::Result<()> {
+        let port = Port::next_available()?;
+        let wait = WaitFor::Port { port };
+
+        let start_time = Instant::now();
+        let actual = wait
+            .prepare(&Output::Inherit)?
+            .block_until_ready(Duration::of(5, DurationUnit::Seconds), || Ok(true));
+        let end_time = Instant::now();
+
+        assert_eq!(actual, Err(DaemonError::ServiceCrashedError));
+        let elapsed = end_time - start_time;
+        assert!(
+            elapsed < std::time::Duration::from_millis(500),
+            "Expected the elapsed time of {:?} to be a very short amount of time.",
+            elapsed
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_refuses_to_wait_for_output_if_it_is_not_captured() -> anyhow::Result<()> {
+        let wait = WaitFor::Output {
+            stream: Stream::Stdout,
+            pattern: "Listening".to_owned(),
+        };
+
+        let actual = block_until_ready(
+            &wait,
+            &Output::Inherit,
+            Duration::of(100, DurationUnit::Milliseconds),
+        );
+
+        assert_eq!(actual, Err(DaemonError::OutputNotCapturedError));
+        Ok(())
+    }
+
+    #[test]
+    fn test_wait_for_all() -> anyhow::Result<()> {
+        let port = Port::next_available()?;
+        let wait = WaitFor::All {
+            conditions: vec![
+                WaitFor::Time {
+                    duration: Duration::of(200, DurationUnit::Milliseconds),
+                },
+                WaitFor::Port { port },
+            ],
+        };
+
+        let listener = thread::spawn(move || {
+            let socket_address = net::SocketAddrV6::new(net::Ipv6Addr::LOCALHOST, port.0, 0, 0);
+            let listener = net::TcpListener::bind(socket_address).unwrap();
+            listener.accept().unwrap(); // block until we receive a connection
+        });
+
+        let start_time = Instant::now();
+        block_until_ready(
+            &wait,
+            &Output::Inherit,
+            Duration::of(1, DurationUnit::Seconds),
+        )?;
+        let end_time = Instant::now();
+
+        listener.join().unwrap();
+        let elapsed = end_time - start_time;
+        assert!(
+            elapsed >= std::time::Duration::from_millis(200),
+            "Expected the elapsed time of {:?} to be at least 200ms.",
+            elapsed
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_time_out_waiting_for_all_reports_the_pending_condition() -> anyhow::Result<()> {
+        let port = Port::next_available()?;
+        let wait = WaitFor::All {
+            conditions: vec![WaitFor::AMoment, WaitFor::Port { port }],
+        };
+
+        let actual = block_until_ready(
+            &wait,
+            &Output::Inherit,
+            Duration::of(300, DurationUnit::Milliseconds),
+        );
+
+        assert_eq!(actual, Err(DaemonError::TimeOut(WaitFor::Port { port })));
+        Ok(())
+    }
+
+    #[test]
+    fn test_wait_for_any() -> anyhow::Result<()> {
+        let port = Port::next_available()?;
+        let wait = WaitFor::Any {
+            conditions: vec![
+                WaitFor::Port { port },
+                WaitFor::Time {
+                    duration: Duration::of(200, DurationUnit::Milliseconds),
+                },
+            ],
+        };
+
+        let start_time = Instant::now();
+        block_until_ready(
+            &wait,
+            &Output::Inherit,
+            Duration::of(1, DurationUnit::Seconds),
+        )?;
+        let end_time = Instant::now();
+
+        let elapsed = end_time - start_time;
+        assert!(
+            elapsed < std::time::Duration::from_millis(750),
+            "Expected the elapsed time of {:?} to be approximately 200ms.",
+            elapsed
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_time_out_waiting_for_any_reports_every_condition() -> anyhow::Result<()> {
+        let port_a = Port::next_available()?;
+        let port_b = Port::next_available()?;
+        let wait = WaitFor::Any {
+            conditions: vec![
+                WaitFor::Port { port: port_a },
+                WaitFor::Port { port: port_b },
+            ],
+        };
+
+        let actual = block_until_ready(
+            &wait,
+            &Output::Inherit,
+            Duration::of(100, DurationUnit::Milliseconds),
+        );
+
+        assert_eq!(actual, Err(DaemonError::TimeOut(wait.clone())));
+        Ok(())
+    }
+
+    #[test]
+    fn test_refuses_to_wait_for_any_of_nothing() {
+        let wait = WaitFor::Any { conditions: vec![] };
+
+        let actual = block_until_ready(
+            &wait,
+            &Output::Inherit,
+            Duration::of(100, DurationUnit::Milliseconds),
+        );
+
+        assert_eq!(actual, Err(DaemonError::NothingToWaitForError));
+    }
+
+    #[test]
+    fn test_wait_for_a_sequence() -> anyhow::Result<()> {
+        let wait = WaitFor::Sequence {
+            conditions: vec![
+                WaitFor::Time {
+                    duration: Duration::of(300, DurationUnit::Milliseconds),
+                },
+                WaitFor::Time {
+                    duration: Duration::of(300, DurationUnit::Milliseconds),
+                },
+            ],
+        };
+
+        let start_time = Instant::now();
+        block_until_ready(
+            &wait,
+            &Output::Inherit,
+            Duration::of(2, DurationUnit::Seconds),
+        )?;
+        let end_time = Instant::now();
+
+        let elapsed = end_time - start_time;
+        assert!(
+            elapsed >= std::time::Duration::from_millis(600),
+            "Expected the elapsed time of {:?} to be at least 600ms.",
+            elapsed
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_a_sequence_shares_the_timeout() -> anyhow::Result<()> {
+        let second = WaitFor::Time {
+            duration: Duration::of(600, DurationUnit::Milliseconds),
+        };
+        let wait = WaitFor::Sequence {
+            conditions: vec![
+                WaitFor::Time {
+                    duration: Duration::of(600, DurationUnit::Milliseconds),
+                },
+                second.clone(),
+            ],
+        };
+
+        let start_time = Instant::now();
+        let actual = block_until_ready(
+            &wait,
+            &Output::Inherit,
+            Duration::of(1, DurationUnit::Seconds),
+        );
+        let end_time = Instant::now();
+
+        assert_eq!(actual, Err(DaemonError::TimeOut(second)));
+        let elapsed = end_time - start_time;
+        assert!(
+            elapsed < std::time::Duration::from_millis(1500),
+            "Expected the elapsed time of {:?} to be approximately 1s.",
+            elapsed
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_describes_composite_conditions() {
+        let wait = WaitFor::Sequence {
+            conditions: vec![
+                WaitFor::AMoment,
+                WaitFor::Any {
+                    conditions: vec![
+                        WaitFor::Port { port: Port(8080) },
+                        WaitFor::Port { port: Port(8443) },
+                    ],
+                },
+            ],
+        };
+
+        assert_eq!(
+            wait.to_string(),
+            "(a moment, then any of (port 8080, port 8443))"
+        );
+    }
 }
@@ -0,0 +1,61 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use sandcastles::*;
+
+#[test]
+fn starts_services_with_the_client_path_and_chosen_variables() -> anyhow::Result<()> {
+    let daemon_socket_dir = tempfile::Builder::new()
+        .prefix("sandcastles-test-daemon")
+        .tempdir()?;
+    let daemon_socket = daemon_socket_dir.path().join("socket");
+    let executable = env!("CARGO_BIN_EXE_sandcastles");
+    let mut client = Client::connect_or_spawn_with(&daemon_socket, Path::new(executable))?;
+
+    // the daemon is already running, so it can only find this through the
+    // client's `PATH`
+    let command_dir = tempfile::tempdir()?;
+    let output_file = command_dir.path().join("output.txt");
+    fs::write(
+        command_dir.path().join("greet"),
+        "#!/bin/sh\necho \"$GREETING\" > \"$OUTPUT_FILE\"\nexec sleep 30\n",
+    )?;
+    fs::set_permissions(
+        command_dir.path().join("greet"),
+        fs::Permissions::from_mode(0o755),
+    )?;
+    let path = std::env::join_paths(std::iter::once(command_dir.path().to_owned()).chain(
+        std::env::split_paths(&std::env::var_os("PATH").unwrap_or_default()),
+    ))?;
+    let status = Command::new(executable)
+        .arg("--socket-path")
+        .arg(&daemon_socket)
+        .arg("start")
+        .arg("--pass-env")
+        .arg("GREETING")
+        .arg("--env")
+        .arg(format!("OUTPUT_FILE={}", output_file.display()))
+        .arg("greet")
+        .env("PATH", path)
+        .env("GREETING", "hello")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()?;
+
+    let start_time = Instant::now();
+    let output = loop {
+        match fs::read_to_string(&output_file) {
+            Ok(output) if !output.is_empty() => break Some(output),
+            _ if start_time.elapsed() > Duration::from_secs(3) => break None,
+            _ => std::thread::sleep(Duration::from_millis(100)),
+        }
+    };
+    client.shutdown()?;
+
+    assert!(status.success(), "the command failed");
+    assert_eq!(output.as_deref(), Some("hello\n"));
+    Ok(())
+}
@@ -25,6 +25,7 @@ fn example_program() -> anyhow::Result<()> {
             name: Some("hello".parse()?),
             service: http_hello_world(),
             wait: WaitFor::Port { port: SERVER_PORT },
+            ..Default::default()
         })?;
 
         assert!(
@@ -59,6 +60,6 @@ pub fn http_hello_world() -> Service {
     Service::Program(Program {
         command: "node".into(),
         arguments: vec![server_script.into()],
-        environment: Default::default(),
+        ..Default::default()
     })
 }
@@ -2,4 +2,7 @@
 
 trap 'echo ignoring' INT TERM
 
-sleep 60
+# the signal is sent to `sleep` too, so we keep going if it stops
+while true; do
+  sleep 60 & wait $!
+done
@@ -0,0 +1,87 @@
+use std::fs;
+use std::process::{Command, Stdio};
+
+use sandcastles::*;
+
+#[test]
+fn runs_a_command_with_services_and_then_stops_them() -> anyhow::Result<()> {
+    let daemon_socket_dir = tempfile::Builder::new()
+        .prefix("sandcastles-test-daemon")
+        .tempdir()?;
+    let daemon_socket = daemon_socket_dir.path().join("socket");
+    let listed_file = daemon_socket_dir.path().join("listed.txt");
+    let executable = env!("CARGO_BIN_EXE_sandcastles");
+
+    let status = Command::new(executable)
+        .arg("--socket-path")
+        .arg(&daemon_socket)
+        .arg("run")
+        .arg("--service")
+        .arg("one=sleep 30")
+        .arg("--service")
+        .arg("two=sleep 30")
+        .arg("--")
+        .arg("sh")
+        .arg("-c")
+        .arg(format!(
+            "{} --socket-path {} list --format json > {}; exit 3",
+            executable,
+            daemon_socket.display(),
+            listed_file.display()
+        ))
+        .stderr(Stdio::null())
+        .status()?;
+
+    let mut client = Client::connect_to(&daemon_socket)?;
+    let remaining = client.list()?;
+    client.shutdown()?;
+
+    assert_eq!(status.code(), Some(3));
+    let listed: Vec<ServiceDetails> = serde_json::from_str(&fs::read_to_string(listed_file)?)?;
+    assert_eq!(
+        listed
+            .into_iter()
+            .map(|service| service.name.to_string())
+            .collect::<Vec<_>>(),
+        vec!["one", "two"]
+    );
+    assert_eq!(remaining, vec![]);
+    Ok(())
+}
+
+#[test]
+fn stops_the_services_if_one_does_not_become_ready() -> anyhow::Result<()> {
+    let daemon_socket_dir = tempfile::Builder::new()
+        .prefix("sandcastles-test-daemon")
+        .tempdir()?;
+    let daemon_socket = daemon_socket_dir.path().join("socket");
+    let ran_file = daemon_socket_dir.path().join("ran.txt");
+    let port = Port::next_available()?;
+
+    let status = Command::new(env!("CARGO_BIN_EXE_sandcastles"))
+        .arg("--socket-path")
+        .arg(&daemon_socket)
+        .arg("run")
+        .arg("--service")
+        .arg("one=sleep 30")
+        .arg("--service")
+        .arg("two=sleep 30")
+        .arg("--wait")
+        .arg(format!("two=port:{}", port))
+        .arg("--timeout")
+        .arg("500ms")
+        .arg("--")
+        .arg("touch")
+        .arg(&ran_file)
+        .stderr(Stdio::null())
+        .status()?;
+
+    let mut client = Client::connect_to(&daemon_socket)?;
+    let remaining = client.list()?;
+    client.shutdown()?;
+
+    assert_eq!(status.code(), Some(1));
+    assert!(!ran_file.exists());
+    assert_eq!(remaining, vec![]);
+    Ok(())
+}
@@ -0,0 +1,121 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Instant;
+
+use sandcastles::timing::Duration;
+use sandcastles::*;
+
+#[test]
+fn spawns_the_daemon_on_first_use() -> anyhow::Result<()> {
+    let daemon_socket_dir = tempfile::Builder::new()
+        .prefix("sandcastles-test-daemon")
+        .tempdir()?;
+    let daemon_socket = daemon_socket_dir.path().join("socket");
+    let executable = Path::new(env!("CARGO_BIN_EXE_sandcastles"));
+
+    let mut client = Client::connect_or_spawn_with(&daemon_socket, executable)?;
+    client.ping()?;
+
+    let mut second_client = Client::connect_or_spawn_with(&daemon_socket, executable)?;
+    second_client.ping()?;
+
+    assert!(
+        daemon_socket_dir.path().join("daemon.log").exists(),
+        "the daemon log has not been created"
+    );
+
+    client.shutdown()?;
+    Ok(())
+}
+
+#[test]
+fn does_not_spawn_the_daemon_just_to_inspect_services() -> anyhow::Result<()> {
+    let daemon_socket_dir = tempfile::Builder::new()
+        .prefix("sandcastles-test-daemon")
+        .tempdir()?;
+    let daemon_socket = daemon_socket_dir.path().join("socket");
+
+    for arguments in [&["list"][..], &["stop", "thing"], &["logs", "thing"]] {
+        let status = Command::new(env!("CARGO_BIN_EXE_sandcastles"))
+            .arg("--socket-path")
+            .arg(&daemon_socket)
+            .args(arguments)
+            .stderr(Stdio::null())
+            .status()?;
+
+        assert!(!status.success(), "{:?} succeeded", arguments);
+    }
+    assert!(
+        !daemon_socket.exists(),
+        "the daemon was spawned when it wasn't needed"
+    );
+    Ok(())
+}
+
+#[test]
+fn reports_a_daemon_that_fails_to_start_straight_away() -> anyhow::Result<()> {
+    let daemon_socket_dir = tempfile::Builder::new()
+        .prefix("sandcastles-test-daemon")
+        .tempdir()?;
+    let daemon_socket = daemon_socket_dir.path().join("socket");
+    // the daemon can't take the lock if there's a directory in the way
+    fs::create_dir(daemon_socket_dir.path().join("socket.lock"))?;
+
+    let start_time = Instant::now();
+    let output = Command::new(env!("CARGO_BIN_EXE_sandcastles"))
+        .arg("--socket-path")
+        .arg(&daemon_socket)
+        .arg("daemon")
+        .output()?;
+
+    assert!(!output.status.success(), "the command succeeded");
+    assert!(
+        start_time.elapsed() < Duration::DAEMON_START_TIMEOUT.into(),
+        "the command waited for the daemon to time out"
+    );
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(
+        stderr.contains("the daemon stopped before it was ready"),
+        "unexpected error: {}",
+        stderr
+    );
+    Ok(())
+}
+
+#[test]
+fn daemonizes_the_daemon_by_default() -> anyhow::Result<()> {
+    let daemon_socket_dir = tempfile::Builder::new()
+        .prefix("sandcastles-test-daemon")
+        .tempdir()?;
+
+    // each daemon gets its own PID file, even if their sockets are together
+    for socket_name in ["one.socket", "two.socket"] {
+        let daemon_socket = daemon_socket_dir.path().join(socket_name);
+
+        let output = Command::new(env!("CARGO_BIN_EXE_sandcastles"))
+            .arg("--socket-path")
+            .arg(&daemon_socket)
+            .arg("daemon")
+            .stderr(Stdio::null())
+            .output()?;
+
+        assert!(output.status.success(), "the command failed");
+        let stdout = String::from_utf8(output.stdout)?;
+        let pid_file = fs::read_to_string(
+            daemon_socket_dir
+                .path()
+                .join(format!("{}.pid", socket_name)),
+        )?;
+        assert_eq!(
+            stdout,
+            format!("socket: {}\npid: {}", daemon_socket.display(), pid_file)
+        );
+    }
+
+    for socket_name in ["one.socket", "two.socket"] {
+        let mut client = Client::connect_to(&daemon_socket_dir.path().join(socket_name))?;
+        client.shutdown()?;
+    }
+    Ok(())
+}